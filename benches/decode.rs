@@ -0,0 +1,69 @@
+//! Decode throughput over a random opcode corpus, run with `cargo bench`.
+//!
+//! Timed like criterion would, a warm up followed by a number of samples,
+//! reporting the spread of the throughput over them, and whether the
+//! fastest sample meets the target throughput.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sim86::decode;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const CORPUS_SIZE: usize = 4 * 1024 * 1024;
+const SAMPLES: usize = 20;
+/// Throughput the decoder is expected to reach, in MB/s
+const TARGET: f64 = 100.0;
+
+/// Decodes the whole corpus, skipping a byte wherever decoding fails
+fn decode_all(corpus: &[u8]) -> usize {
+    let mut offset = 0;
+    let mut decoded = 0;
+    while offset < corpus.len() {
+        match decode(&corpus[offset..], offset) {
+            Ok((instruction, size)) => {
+                // Kept, or the operands would never need to be worked out
+                black_box(instruction);
+                offset += size;
+                decoded += 1;
+            }
+            Err(_) => offset += 1,
+        }
+    }
+    decoded
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(86);
+    let corpus: Vec<u8> = (0..CORPUS_SIZE).map(|_| rng.gen()).collect();
+
+    let warm_up = Instant::now();
+    while warm_up.elapsed() < Duration::from_secs(1) {
+        black_box(decode_all(black_box(&corpus)));
+    }
+
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(decode_all(black_box(&corpus)));
+            start.elapsed()
+        })
+        .collect();
+    samples.sort();
+
+    let throughput = |time: Duration| CORPUS_SIZE as f64 / time.as_secs_f64() / 1e6;
+    let (fastest, median, slowest) = (samples[0], samples[SAMPLES / 2], samples[SAMPLES - 1]);
+    println!("decode/random {} MiB", CORPUS_SIZE / (1024 * 1024));
+    println!("  time:  [{fastest:.2?} {median:.2?} {slowest:.2?}]");
+    println!(
+        "  thrpt: [{:.1} MB/s {:.1} MB/s {:.1} MB/s]",
+        throughput(slowest),
+        throughput(median),
+        throughput(fastest)
+    );
+    let verdict = if throughput(fastest) >= TARGET {
+        "met"
+    } else {
+        "missed"
+    };
+    println!("  target: {TARGET:.1} MB/s {verdict}");
+}
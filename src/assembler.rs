@@ -0,0 +1,854 @@
+use crate::table::{layouts, Field};
+use crate::{
+    Address, EAddress, FarPointer, Immediate, Instruction, Jump, Location, Op, Register, Repeat,
+    Source,
+};
+use core::fmt;
+use nom::{
+    branch::alt,
+    bytes::complete::{tag, tag_no_case, take_while, take_while1},
+    character::complete::{char, digit1, hex_digit1, one_of, space0},
+    combinator::{all_consuming, map, map_opt, map_res, opt, recognize, rest, value, verify},
+    multi::{many0, separated_list0, separated_list1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
+    IResult,
+};
+use std::{collections::HashMap, fmt::Display};
+
+/// Assembles nasm syntax into machine code. It takes the assembly `disassemble` produces,
+/// along with labels, `db`, `dw`, `org` and `times`.
+///
+/// Labels can be used before they are defined. Jumps and displacements get the shortest encoding
+/// that reaches, which depends on where the labels end up, so the program is laid out
+/// until the labels stay put and then laid out once more to emit it.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let lines = source
+        .lines()
+        .zip(1..)
+        .map(|(text, line)| match parse_line(text) {
+            Ok((_, parsed)) => Ok((line, parsed)),
+            Err(_) => Err(AssembleError::Syntax { line }),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut symbols = Symbols::new();
+    for _ in 0..MAX_PASSES {
+        let (_, placed) = lay_out(&lines, &symbols, false)?;
+        if placed == symbols {
+            return lay_out(&lines, &symbols, true).map(|(program, _)| program);
+        }
+        symbols = placed;
+    }
+    let (_, placed) = lay_out(&lines, &symbols, false)?;
+    let line = lines
+        .iter()
+        .find(|(_, parsed)| {
+            parsed
+                .label
+                .is_some_and(|label| placed.get(label) != symbols.get(label))
+        })
+        .map_or(0, |(line, _)| *line);
+    Err(AssembleError::Unsettled { line })
+}
+
+/// Layouts after which the labels are given up on ever staying put
+const MAX_PASSES: usize = 16;
+
+/// Addresses of the labels, by name
+type Symbols<'a> = HashMap<&'a str, i64>;
+
+/// Reason why the assembly couldn't be assembled.
+///
+/// `line` is the line number of the offending line, counting from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssembleError {
+    Syntax {
+        line: usize,
+    },
+    /// Anything but `bits 16`
+    UnsupportedBits {
+        line: usize,
+    },
+    UndefinedLabel {
+        line: usize,
+        label: String,
+    },
+    DuplicateLabel {
+        line: usize,
+        label: String,
+    },
+    /// No encoding of the operation takes these operands
+    InvalidOperands {
+        line: usize,
+    },
+    /// A memory operand with nothing to tell whether it is a byte or a word
+    UnsizedOperand {
+        line: usize,
+    },
+    /// A value too large for its place, or a jump too far away
+    OutOfRange {
+        line: usize,
+    },
+    /// The size of the line keeps changing the address of the label it defines
+    Unsettled {
+        line: usize,
+    },
+}
+impl AssembleError {
+    pub fn line(&self) -> usize {
+        match *self {
+            AssembleError::Syntax { line }
+            | AssembleError::UnsupportedBits { line }
+            | AssembleError::UndefinedLabel { line, .. }
+            | AssembleError::DuplicateLabel { line, .. }
+            | AssembleError::InvalidOperands { line }
+            | AssembleError::UnsizedOperand { line }
+            | AssembleError::OutOfRange { line }
+            | AssembleError::Unsettled { line } => line,
+        }
+    }
+}
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssembleError::Syntax { line } => write!(f, "syntax error on line {line}"),
+            AssembleError::UnsupportedBits { line } => {
+                write!(f, "only bits 16 is supported, on line {line}")
+            }
+            AssembleError::UndefinedLabel { line, label } => {
+                write!(f, "undefined label {label} on line {line}")
+            }
+            AssembleError::DuplicateLabel { line, label } => {
+                write!(f, "label {label} redefined on line {line}")
+            }
+            AssembleError::InvalidOperands { line } => {
+                write!(
+                    f,
+                    "invalid combination of operation and operands on line {line}"
+                )
+            }
+            AssembleError::UnsizedOperand { line } => {
+                write!(f, "operation size not specified on line {line}")
+            }
+            AssembleError::OutOfRange { line } => write!(f, "value out of range on line {line}"),
+            AssembleError::Unsettled { line } => {
+                write!(f, "label address never settles on line {line}")
+            }
+        }
+    }
+}
+impl std::error::Error for AssembleError {}
+
+/// Emits the program with the labels at the given addresses, along with where they end up.
+/// Until the `last` layout a label that isn't known yet is taken to be at the current address.
+fn lay_out<'a>(
+    lines: &[(usize, Line<'a>)],
+    symbols: &Symbols<'a>,
+    last: bool,
+) -> Result<(Vec<u8>, Symbols<'a>), AssembleError> {
+    // The origin applies to the whole program, wherever it is set
+    let mut origin = 0;
+    for (line, parsed) in lines {
+        if let Some(Item::Org(address)) = &parsed.item {
+            let context = Context {
+                line: *line,
+                symbols: &Symbols::new(),
+                here: 0,
+                origin: 0,
+                last: true,
+            };
+            origin = context.eval(address)?;
+        }
+    }
+
+    let mut program = Vec::new();
+    let mut placed = Symbols::new();
+    for (line, parsed) in lines {
+        let mut context = Context {
+            line: *line,
+            symbols,
+            here: origin + program.len() as i64,
+            origin,
+            last,
+        };
+        if let Some(label) = parsed.label {
+            if placed.insert(label, context.here).is_some() {
+                return Err(AssembleError::DuplicateLabel {
+                    line: *line,
+                    label: label.to_string(),
+                });
+            }
+        }
+        let count = match &parsed.times {
+            Some(count) => context.eval(count)?,
+            None => 1,
+        };
+        if count < 0 {
+            return Err(AssembleError::OutOfRange { line: *line });
+        }
+        for _ in 0..count {
+            context.here = origin + program.len() as i64;
+            match &parsed.item {
+                Some(Item::Bits(bits)) if context.eval(bits)? != 16 => {
+                    return Err(AssembleError::UnsupportedBits { line: *line })
+                }
+                Some(Item::Bits(_) | Item::Org(_)) | None => {}
+                Some(Item::Data { is_word, values }) => {
+                    for data in values {
+                        match data {
+                            Data::Text(text) => {
+                                program.extend(text.bytes());
+                                // Strings are padded to whole words
+                                if *is_word && text.len() % 2 == 1 {
+                                    program.push(0);
+                                }
+                            }
+                            Data::Value(value) if *is_word => {
+                                let value = context.word(context.eval(value)?)?;
+                                program.extend(value.to_le_bytes())
+                            }
+                            Data::Value(value) => program.push(context.byte(context.eval(value)?)?),
+                        }
+                    }
+                }
+                Some(Item::Instruction(statement)) => program.extend(statement.encode(&context)?),
+            }
+        }
+    }
+    Ok((program, placed))
+}
+
+/// What expressions are evaluated against
+struct Context<'s, 'a> {
+    line: usize,
+    symbols: &'s Symbols<'a>,
+    /// Address of the start of the line, `$`
+    here: i64,
+    /// Address of the start of the program, `$$`
+    origin: i64,
+    /// Whether the labels are final, and any missing is an error
+    last: bool,
+}
+impl Context<'_, '_> {
+    fn eval(&self, expr: &Expr) -> Result<i64, AssembleError> {
+        Ok(match expr {
+            Expr::Number(number) => *number,
+            Expr::Here => self.here,
+            Expr::Start => self.origin,
+            Expr::Label(label) => match self.symbols.get(label) {
+                Some(&address) => address,
+                None if !self.last => self.here,
+                None => {
+                    return Err(AssembleError::UndefinedLabel {
+                        line: self.line,
+                        label: label.to_string(),
+                    })
+                }
+            },
+            Expr::Neg(expr) => self.eval(expr)?.wrapping_neg(),
+            Expr::Sum(terms) => terms.iter().try_fold(0i64, |sum, term| {
+                self.eval(term).map(|term| sum.wrapping_add(term))
+            })?,
+        })
+    }
+
+    /// Value that fits in a byte, signed or not
+    fn byte(&self, value: i64) -> Result<u8, AssembleError> {
+        match value {
+            -0x80..=0xff => Ok(value as u8),
+            _ => Err(AssembleError::OutOfRange { line: self.line }),
+        }
+    }
+
+    /// Value that fits in a word, signed or not
+    fn word(&self, value: i64) -> Result<u16, AssembleError> {
+        match value {
+            -0x8000..=0xffff => Ok(value as u16),
+            _ => Err(AssembleError::OutOfRange { line: self.line }),
+        }
+    }
+
+    fn address(&self, memory: &Memory) -> Result<EAddress, AssembleError> {
+        let displacement = match &memory.displacement {
+            Some(displacement) => self.word(self.eval(displacement)?)?,
+            None => 0,
+        };
+        Ok(match memory.base {
+            None => EAddress::Direct(displacement),
+            // A zero displacement needs no byte, except for [bp] which the encoder takes care of
+            Some(base) if displacement == 0 => EAddress::Bare(base),
+            Some(base) => EAddress::WithOffset(base, displacement as i16),
+        })
+    }
+}
+
+/// A line of assembly, everything on it is optional
+struct Line<'a> {
+    label: Option<&'a str>,
+    times: Option<Expr<'a>>,
+    item: Option<Item<'a>>,
+}
+
+enum Item<'a> {
+    Bits(Expr<'a>),
+    Org(Expr<'a>),
+    /// `db` or `dw`
+    Data {
+        is_word: bool,
+        values: Vec<Data<'a>>,
+    },
+    Instruction(Statement<'a>),
+}
+
+enum Data<'a> {
+    Text(&'a str),
+    Value(Expr<'a>),
+}
+
+enum Expr<'a> {
+    Number(i64),
+    Label(&'a str),
+    /// `$`
+    Here,
+    /// `$$`
+    Start,
+    Neg(Box<Expr<'a>>),
+    Sum(Vec<Expr<'a>>),
+}
+
+/// An instruction as written, with its operands in the order of the syntax
+struct Statement<'a> {
+    lock: bool,
+    repeat: Option<Repeat>,
+    /// Segment override prefix written on its own, rather than on the memory operand
+    segment: Option<Register>,
+    op: Op,
+    /// Width of the displacement of a jump, when `short` or `near` gives it
+    is_word: Option<bool>,
+    operands: Vec<Operand<'a>>,
+}
+
+enum Operand<'a> {
+    Reg(Register),
+    Mem(Memory<'a>),
+    Imm {
+        is_word: Option<bool>,
+        value: Expr<'a>,
+    },
+    /// `segment:offset`
+    Far {
+        segment: Expr<'a>,
+        offset: Expr<'a>,
+    },
+}
+impl Operand<'_> {
+    /// Width of the operand, when it tells one
+    fn is_word(&self) -> Option<bool> {
+        match self {
+            Operand::Reg(register) => Some(!matches!(
+                register,
+                Register::Al
+                    | Register::Cl
+                    | Register::Dl
+                    | Register::Bl
+                    | Register::Ah
+                    | Register::Ch
+                    | Register::Dh
+                    | Register::Bh
+            )),
+            Operand::Mem(memory) => memory.is_word,
+            Operand::Imm { is_word, .. } => *is_word,
+            Operand::Far { .. } => None,
+        }
+    }
+}
+
+struct Memory<'a> {
+    is_word: Option<bool>,
+    segment: Option<Register>,
+    base: Option<Address>,
+    displacement: Option<Expr<'a>>,
+}
+
+impl Statement<'_> {
+    fn encode(&self, context: &Context) -> Result<Vec<u8>, AssembleError> {
+        let mut operands: Vec<&Operand> = self.operands.iter().collect();
+        match self.op {
+            // The operands of these are the other way around in the syntax
+            Op::Out | Op::Esc => operands.reverse(),
+            // Either way around is the same exchange, the shorter encoding wins
+            Op::Xchg => {
+                let swapped: Vec<&Operand> = operands.iter().rev().copied().collect();
+                return match (
+                    self.encode_operands(context, &operands),
+                    self.encode_operands(context, &swapped),
+                ) {
+                    (Ok(bytes), Ok(shorter)) if shorter.len() < bytes.len() => Ok(shorter),
+                    (Ok(bytes), _) | (Err(_), Ok(bytes)) => Ok(bytes),
+                    (Err(err), Err(_)) => Err(err),
+                };
+            }
+            _ => {}
+        }
+        self.encode_operands(context, &operands)
+    }
+
+    /// Encodes the operation with the operands in the order of the destination and source
+    fn encode_operands(
+        &self,
+        context: &Context,
+        operands: &[&Operand],
+    ) -> Result<Vec<u8>, AssembleError> {
+        let line = context.line;
+        let (destination, source) = match *operands {
+            [] => (None, None),
+            [source @ (Operand::Imm { .. } | Operand::Far { .. })] => (None, Some(source)),
+            [destination] => (Some(destination), None),
+            [destination, source] => (Some(destination), Some(source)),
+            _ => return Err(AssembleError::InvalidOperands { line }),
+        };
+        // A far operation with a pointer operand is just the direct form of it
+        let op = match (self.op, source) {
+            (Op::CallFar, Some(Operand::Far { .. })) => Op::Call,
+            (Op::JmpFar, Some(Operand::Far { .. })) => Op::Jmp,
+            (op, _) => op,
+        };
+
+        // An explicit size goes first, then the destination. The count of a shift has no say.
+        let is_word = self
+            .is_word
+            .or_else(|| {
+                operands
+                    .iter()
+                    .filter(|operand| !matches!(operand, Operand::Reg(_)))
+                    .find_map(|operand| operand.is_word())
+            })
+            .or_else(|| destination.and_then(Operand::is_word))
+            .or_else(|| source.filter(|_| !op.is_shift()).and_then(Operand::is_word));
+        let widths: &[bool] = match is_word {
+            Some(true) => &[true],
+            Some(false) => &[false],
+            None => {
+                let in_memory = operands
+                    .iter()
+                    .any(|operand| matches!(operand, Operand::Mem(_)));
+                let sized = layouts()
+                    .iter()
+                    .any(|layout| layout.op == op && layout.w.value().is_none());
+                if in_memory && sized {
+                    return Err(AssembleError::UnsizedOperand { line });
+                }
+                &[false, true]
+            }
+        };
+
+        let mut error = None;
+        for &is_word in widths {
+            match self.instruction(context, op, is_word, destination, source) {
+                Ok(instruction) => match instruction.try_encode() {
+                    Some(bytes) => return Ok(bytes),
+                    None if is_relative(op) => {
+                        error.get_or_insert(AssembleError::OutOfRange { line });
+                    }
+                    None => {}
+                },
+                Err(err) => {
+                    error.get_or_insert(err);
+                }
+            }
+        }
+        Err(error.unwrap_or(AssembleError::InvalidOperands { line }))
+    }
+
+    fn instruction(
+        &self,
+        context: &Context,
+        op: Op,
+        is_word: bool,
+        destination: Option<&Operand>,
+        source: Option<&Operand>,
+    ) -> Result<Instruction, AssembleError> {
+        let line = context.line;
+        let mut segment = self.segment;
+        let mut location = |operand: &Operand| match operand {
+            Operand::Reg(register) => Ok(Location::Reg(*register)),
+            Operand::Mem(memory) => {
+                if memory.segment.is_some() {
+                    if segment.is_some() {
+                        return Err(AssembleError::InvalidOperands { line });
+                    }
+                    segment = memory.segment;
+                }
+                context.address(memory).map(Location::Addr)
+            }
+            _ => Err(AssembleError::InvalidOperands { line }),
+        };
+        let destination = destination.map(&mut location).transpose()?;
+        let source = match source {
+            None => None,
+            Some(Operand::Imm { value, .. }) if is_relative(op) => {
+                let target = context.eval(value)?;
+                Some(Source::Jump(Jump {
+                    // Only the target matters to the encoder
+                    displacement: 0,
+                    target: target as usize,
+                }))
+            }
+            Some(Operand::Imm { value, .. }) => {
+                let value = context.eval(value)?;
+                Some(Source::Imm(if is_word {
+                    Immediate::Word(context.word(value)?)
+                } else {
+                    Immediate::Byte(context.byte(value)?)
+                }))
+            }
+            Some(Operand::Far { segment, offset }) => Some(Source::Far(FarPointer {
+                segment: context.word(context.eval(segment)?)?,
+                offset: context.word(context.eval(offset)?)?,
+            })),
+            Some(operand) => Some(Source::Loc(location(operand)?)),
+        };
+        Ok(Instruction {
+            address: context.here as usize,
+            size: 0,
+            is_word,
+            lock: self.lock,
+            segment,
+            repeat: self.repeat,
+            operation: op,
+            destination,
+            source: source.into(),
+        })
+    }
+}
+
+/// Whether the operation has a form that branches relative to the next instruction
+fn is_relative(op: Op) -> bool {
+    layouts()
+        .iter()
+        .any(|layout| layout.op == op && matches!(layout.source, Some(Field::Rel8 | Field::Rel16)))
+}
+
+fn parse_line(text: &str) -> IResult<&str, Line<'_>> {
+    let label = terminated(identifier, token(char(':')));
+    let times = preceded(keyword("times"), expression);
+    let comment = preceded(char(';'), rest);
+    map(
+        all_consuming(delimited(
+            space0,
+            pair(pair(opt(label), opt(times)), opt(item)),
+            opt(comment),
+        )),
+        |((label, times), item)| Line { label, times, item },
+    )(text)
+}
+
+fn item(i: &str) -> IResult<&str, Item<'_>> {
+    let data_value = alt((map(token(string), Data::Text), map(expression, Data::Value)));
+    alt((
+        map(preceded(keyword("bits"), expression), Item::Bits),
+        map(preceded(keyword("org"), expression), Item::Org),
+        map(
+            pair(
+                alt((value(false, keyword("db")), value(true, keyword("dw")))),
+                separated_list1(token(char(',')), data_value),
+            ),
+            |(is_word, values)| Item::Data { is_word, values },
+        ),
+        map(statement, Item::Instruction),
+    ))(i)
+}
+
+fn statement(i: &str) -> IResult<&str, Statement<'_>> {
+    enum Prefix {
+        Lock,
+        Repeat(Repeat),
+        Segment(Register),
+    }
+    let prefix = map_opt(token(identifier), |name| {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "lock" => Prefix::Lock,
+            "rep" | "repe" | "repz" => Prefix::Repeat(Repeat::Rep),
+            "repne" | "repnz" => Prefix::Repeat(Repeat::Repne),
+            _ => Prefix::Segment(segment_register(name)?),
+        })
+    });
+    let (i, prefixes) = many0(prefix)(i)?;
+    let (i, (op, is_word)) = mnemonic(i)?;
+    let (i, operands) = separated_list0(token(char(',')), operand)(i)?;
+
+    let mut statement = Statement {
+        lock: false,
+        repeat: None,
+        segment: None,
+        op,
+        is_word,
+        operands,
+    };
+    for prefix in prefixes {
+        match prefix {
+            Prefix::Lock => statement.lock = true,
+            Prefix::Repeat(repeat) => statement.repeat = Some(repeat),
+            Prefix::Segment(segment) => statement.segment = Some(segment),
+        }
+    }
+    Ok((i, statement))
+}
+
+/// The operation, along with the width of the displacement of a jump when `short` or `near`
+/// gives it
+fn mnemonic(i: &str) -> IResult<&str, (Op, Option<bool>)> {
+    #[derive(Clone, Copy)]
+    enum Distance {
+        Short,
+        Near,
+        Far,
+    }
+    let distance = alt((
+        value(Distance::Short, keyword("short")),
+        value(Distance::Near, keyword("near")),
+        value(Distance::Far, keyword("far")),
+    ));
+    map_opt(
+        pair(map_opt(token(identifier), operation), opt(distance)),
+        |(op, distance)| match (op, distance) {
+            (op, None) => Some((op, None)),
+            (Op::Call, Some(Distance::Far)) => Some((Op::CallFar, None)),
+            (Op::Jmp, Some(Distance::Far)) => Some((Op::JmpFar, None)),
+            (Op::Call | Op::Jmp, Some(Distance::Near)) => Some((op, Some(true))),
+            // Every relative jump but call has a short form
+            (op, Some(Distance::Short)) if op != Op::Call && is_relative(op) => {
+                Some((op, Some(false)))
+            }
+            _ => None,
+        },
+    )(i)
+}
+
+/// Operation of a mnemonic, including the aliases nasm knows some of them by
+fn operation(mnemonic: &str) -> Option<Op> {
+    let mnemonic = mnemonic.to_ascii_lowercase();
+    let mnemonic = match mnemonic.as_str() {
+        "jz" => "je",
+        "jnz" => "jne",
+        "jc" | "jnae" => "jb",
+        "jnc" | "jae" => "jnb",
+        "jna" => "jbe",
+        "jnbe" => "ja",
+        "jpe" => "jp",
+        "jpo" => "jnp",
+        "jnge" => "jl",
+        "jge" => "jnl",
+        "jng" => "jle",
+        "jnle" => "jg",
+        "loope" => "loopz",
+        "loopne" => "loopnz",
+        "sal" => "shl",
+        "xlatb" => "xlat",
+        mnemonic => mnemonic,
+    };
+    layouts()
+        .iter()
+        .map(|layout| layout.op)
+        .find(|op| op.to_string() == mnemonic)
+}
+
+fn operand(i: &str) -> IResult<&str, Operand<'_>> {
+    let size = alt((value(false, keyword("byte")), value(true, keyword("word"))));
+    let (i, is_word) = opt(size)(i)?;
+    let memory = map(memory, move |memory| {
+        Operand::Mem(Memory { is_word, ..memory })
+    });
+    let immediate = map(expression, move |value| Operand::Imm { is_word, value });
+    if is_word.is_some() {
+        return alt((memory, immediate))(i);
+    }
+    let far = map(
+        separated_pair(expression, token(char(':')), expression),
+        |(segment, offset)| Operand::Far { segment, offset },
+    );
+    alt((memory, map(register, Operand::Reg), far, immediate))(i)
+}
+
+/// `[bx + si + 4]`, optionally preceded by a segment override such as `es:`
+fn memory(i: &str) -> IResult<&str, Memory<'_>> {
+    enum Part<'a> {
+        Reg(Register),
+        Value(Expr<'a>),
+    }
+    let part = || alt((map(register, Part::Reg), map(term, Part::Value)));
+    let parts = pair(part(), many0(pair(token(one_of("+-")), part())));
+    let address = map_opt(parts, |(first, rest)| {
+        let mut registers = Vec::new();
+        let mut terms = Vec::new();
+        for (sign, part) in [('+', first)].into_iter().chain(rest) {
+            match (sign, part) {
+                ('+', Part::Reg(register)) => registers.push(register),
+                // Registers can only be added up
+                (_, Part::Reg(_)) => return None,
+                ('+', Part::Value(term)) => terms.push(term),
+                (_, Part::Value(term)) => terms.push(Expr::Neg(Box::new(term))),
+            }
+        }
+        let displacement = (!terms.is_empty()).then_some(Expr::Sum(terms));
+        Some((base(&registers)?, displacement))
+    });
+    let segment = terminated(
+        map_opt(token(identifier), segment_register),
+        token(char(':')),
+    );
+    map(
+        pair(
+            opt(segment),
+            delimited(token(char('[')), address, token(char(']'))),
+        ),
+        |(segment, (base, displacement))| Memory {
+            is_word: None,
+            segment,
+            base,
+            displacement,
+        },
+    )(i)
+}
+
+/// Base of an effective address made up of the registers, `None` for a direct address
+fn base(registers: &[Register]) -> Option<Option<Address>> {
+    use Register::*;
+    let base = match registers {
+        [] => return Some(None),
+        [Bx, Si] | [Si, Bx] => Address::BxSi,
+        [Bx, Di] | [Di, Bx] => Address::BxDi,
+        [Bp, Si] | [Si, Bp] => Address::BpSi,
+        [Bp, Di] | [Di, Bp] => Address::BpDi,
+        [Si] => Address::Si,
+        [Di] => Address::Di,
+        [Bp] => Address::Bp,
+        [Bx] => Address::Bx,
+        _ => return None,
+    };
+    Some(Some(base))
+}
+
+/// Terms added up or subtracted
+fn expression(i: &str) -> IResult<&str, Expr<'_>> {
+    map(
+        pair(term, many0(pair(token(one_of("+-")), term))),
+        |(first, rest)| {
+            let terms = [first]
+                .into_iter()
+                .chain(rest.into_iter().map(|(sign, term)| match sign {
+                    '-' => Expr::Neg(Box::new(term)),
+                    _ => term,
+                }))
+                .collect();
+            Expr::Sum(terms)
+        },
+    )(i)
+}
+
+fn term(i: &str) -> IResult<&str, Expr<'_>> {
+    alt((
+        map(preceded(token(char('-')), term), |term| {
+            Expr::Neg(Box::new(term))
+        }),
+        preceded(token(char('+')), term),
+        delimited(token(char('(')), expression, token(char(')'))),
+        map(token(number), Expr::Number),
+        // Up to two characters, the first in the low byte
+        map_opt(token(string), |text| match text.len() {
+            1 | 2 => Some(Expr::Number(
+                text.bytes()
+                    .rev()
+                    .fold(0, |sum, byte| sum << 8 | byte as i64),
+            )),
+            _ => None,
+        }),
+        map(token(tag("$$")), |_| Expr::Start),
+        map(token(char('$')), |_| Expr::Here),
+        map(token(identifier), Expr::Label),
+    ))(i)
+}
+
+/// Decimal, or hexadecimal as `0x10` or `10h`, or binary as `0b10`
+fn number(i: &str) -> IResult<&str, i64> {
+    let digits = |radix| move |text: &str| i64::from_str_radix(text, radix);
+    alt((
+        map_res(preceded(tag_no_case("0x"), hex_digit1), digits(16)),
+        map_res(
+            terminated(
+                recognize(pair(digit1, take_while(|c: char| c.is_ascii_hexdigit()))),
+                tag_no_case("h"),
+            ),
+            digits(16),
+        ),
+        map_res(
+            preceded(tag_no_case("0b"), take_while1(|c| c == '0' || c == '1')),
+            digits(2),
+        ),
+        map_res(digit1, digits(10)),
+    ))(i)
+}
+
+/// Quoted with either kind of quotes
+fn string(i: &str) -> IResult<&str, &str> {
+    alt((
+        delimited(char('\''), take_while(|c| c != '\''), char('\'')),
+        delimited(char('"'), take_while(|c| c != '"'), char('"')),
+    ))(i)
+}
+
+fn identifier(i: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        take_while1(|c: char| c.is_ascii_alphabetic() || "._?".contains(c)),
+        take_while(|c: char| c.is_ascii_alphanumeric() || "._?".contains(c)),
+    ))(i)
+}
+
+/// The identifier, in any case
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    token(verify(identifier, move |name: &str| {
+        name.eq_ignore_ascii_case(word)
+    }))
+}
+
+fn register(i: &str) -> IResult<&str, Register> {
+    map_opt(token(identifier), |name| {
+        use Register::*;
+        Some(match name.to_ascii_lowercase().as_str() {
+            "al" => Al,
+            "cl" => Cl,
+            "dl" => Dl,
+            "bl" => Bl,
+            "ah" => Ah,
+            "ch" => Ch,
+            "dh" => Dh,
+            "bh" => Bh,
+            "ax" => Ax,
+            "cx" => Cx,
+            "dx" => Dx,
+            "bx" => Bx,
+            "sp" => Sp,
+            "bp" => Bp,
+            "si" => Si,
+            "di" => Di,
+            _ => segment_register(name)?,
+        })
+    })(i)
+}
+
+fn segment_register(name: &str) -> Option<Register> {
+    Some(match name.to_ascii_lowercase().as_str() {
+        "es" => Register::Es,
+        "cs" => Register::Cs,
+        "ss" => Register::Ss,
+        "ds" => Register::Ds,
+        _ => return None,
+    })
+}
+
+/// The parser followed by any whitespace
+fn token<'a, O>(
+    parser: impl FnMut(&'a str) -> IResult<&'a str, O>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, O> {
+    terminated(parser, space0)
+}
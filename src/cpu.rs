@@ -0,0 +1,543 @@
+use crate::parser::parse_instruction;
+use crate::{
+    Address, DecodeError, EAddress, Immediate, Instruction, Location, Memory, Op, Register, Source,
+};
+use core::{array, fmt};
+use std::fmt::Display;
+use std::ops::Range;
+
+/// State of the 8086 that instructions are executed against
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Cpu {
+    /// ax, cx, dx, bx, sp, bp, si and di, in the order of their encoding
+    registers: [u16; 8],
+    /// es, cs, ss and ds, in the order of their encoding
+    segments: [u16; 4],
+    ip: u16,
+    flags: u16,
+    memory: Memory,
+    /// Physical addresses of the program that `load` copied into memory
+    image: Range<usize>,
+}
+
+impl Cpu {
+    /// Value of the register, with a byte register in the low byte
+    pub fn register(&self, register: Register) -> u16 {
+        let value = register.value() as usize;
+        match register {
+            Register::Es | Register::Cs | Register::Ss | Register::Ds => self.segments[value],
+            Register::Al | Register::Cl | Register::Dl | Register::Bl => {
+                self.registers[value] & 0xff
+            }
+            // ah, ch, dh and bh are the high halves of the first four
+            Register::Ah | Register::Ch | Register::Dh | Register::Bh => {
+                self.registers[value - 4] >> 8
+            }
+            _ => self.registers[value],
+        }
+    }
+
+    /// Sets the register, only the low byte of `value` for a byte register
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        let index = register.value() as usize;
+        match register {
+            Register::Es | Register::Cs | Register::Ss | Register::Ds => {
+                self.segments[index] = value
+            }
+            Register::Al | Register::Cl | Register::Dl | Register::Bl => {
+                let word = &mut self.registers[index];
+                *word = (*word & 0xff00) | (value & 0xff);
+            }
+            Register::Ah | Register::Ch | Register::Dh | Register::Bh => {
+                let word = &mut self.registers[index - 4];
+                *word = (*word & 0x00ff) | (value << 8);
+            }
+            _ => self.registers[index] = value,
+        }
+    }
+
+    pub fn ip(&self) -> u16 {
+        self.ip
+    }
+
+    pub fn flags(&self) -> u16 {
+        self.flags
+    }
+
+    pub fn flag(&self, flag: Flag) -> bool {
+        self.flags & flag.mask() != 0
+    }
+
+    pub fn set_flag(&mut self, flag: Flag, value: bool) {
+        if value {
+            self.flags |= flag.mask();
+        } else {
+            self.flags &= !flag.mask();
+        }
+    }
+
+    /// Copies the program to cs:ip, the image that `run` executes
+    pub fn load(&mut self, program: &[u8]) {
+        let (cs, ip) = (self.register(Register::Cs), self.ip);
+        for (i, &byte) in program.iter().enumerate() {
+            self.memory.write_byte(cs, ip.wrapping_add(i as u16), byte);
+        }
+        let start = Memory::physical(cs, ip);
+        self.image = start..start + program.len();
+    }
+
+    /// Executes instructions from cs:ip until a `hlt`, until cs:ip leaves the loaded image,
+    /// or until `budget` instructions have been executed
+    pub fn run(&mut self, budget: usize) -> StopReason {
+        self.run_with(budget, |_, _| {})
+    }
+
+    /// Same as `run`, calling `executed` after each instruction with the state it left behind
+    pub fn run_with(
+        &mut self,
+        budget: usize,
+        mut executed: impl FnMut(&Cpu, &Instruction),
+    ) -> StopReason {
+        for _ in 0..budget {
+            let at = Memory::physical(self.register(Register::Cs), self.ip);
+            if !self.image.contains(&at) {
+                return StopReason::LeftImage;
+            }
+            let instruction = match self.fetch() {
+                Ok(instruction) => instruction,
+                Err(err) => return StopReason::Decode(err),
+            };
+            if let Err(err) = self.step(&instruction) {
+                return StopReason::Execute(err);
+            }
+            executed(self, &instruction);
+            if instruction.operation == Op::Hlt {
+                return StopReason::Halt;
+            }
+        }
+        StopReason::Budget
+    }
+
+    /// Decodes the instruction at cs:ip, with ip as its address. Only bytes of the loaded image
+    /// are decoded, an instruction running past its end is truncated
+    pub fn fetch(&self) -> Result<Instruction, DecodeError> {
+        let (cs, ip) = (self.register(Register::Cs), self.ip);
+        // Longer than any instruction, unless it repeats prefixes
+        let bytes: [u8; 16] =
+            array::from_fn(|i| self.memory.read_byte(cs, ip.wrapping_add(i as u16)));
+        let left = self.image.end.saturating_sub(Memory::physical(cs, ip));
+        parse_instruction(&bytes[..left.min(bytes.len())], ip as usize)
+            .map(|(instruction, _)| instruction)
+            .map_err(|err| err.at(ip as usize))
+    }
+
+    /// Executes the instruction, which moves ip past it before jumps and calls take effect
+    pub fn step(&mut self, instruction: &Instruction) -> Result<(), ExecuteError> {
+        self.ip = (instruction.address + instruction.size) as u16;
+        let executed = match instruction.operation {
+            Op::Hlt | Op::Nop => Some(()),
+            Op::Push | Op::Pop | Op::Pushf | Op::Popf => self.stack(instruction),
+            Op::Call | Op::CallFar | Op::Jmp | Op::JmpFar | Op::Ret | Op::Retf => {
+                self.transfer(instruction)
+            }
+            Op::Jo
+            | Op::Jno
+            | Op::Jb
+            | Op::Jnb
+            | Op::Je
+            | Op::Jne
+            | Op::Jbe
+            | Op::Ja
+            | Op::Js
+            | Op::Jns
+            | Op::Jp
+            | Op::Jnp
+            | Op::Jl
+            | Op::Jnl
+            | Op::Jle
+            | Op::Jg
+            | Op::Loopnz
+            | Op::Loopz
+            | Op::Loop
+            | Op::Jcxz => self.branch(instruction),
+            _ => self.compute(instruction),
+        };
+        executed.ok_or(ExecuteError::Unsupported {
+            address: instruction.address,
+        })
+    }
+
+    /// Moves, arithmetic and logic, which write their result to the destination
+    fn compute(&mut self, instruction: &Instruction) -> Option<()> {
+        let destination = instruction.destination.as_ref()?;
+        let source = match &instruction.source() {
+            Some(source) => Some(self.read_source(source, instruction)?),
+            None => None,
+        };
+        let op = instruction.operation;
+        let result = match (op, source) {
+            (Op::Mov, Some(value)) => value,
+            (
+                Op::Add
+                | Op::Adc
+                | Op::Sub
+                | Op::Sbb
+                | Op::Cmp
+                | Op::And
+                | Op::Or
+                | Op::Xor
+                | Op::Test,
+                Some(value),
+            ) => {
+                let current = self.read(destination, instruction);
+                self.arithmetic(op, current, value, instruction.is_word)
+            }
+            (Op::Inc | Op::Dec, None) => {
+                let current = self.read(destination, instruction);
+                self.arithmetic(op, current, 1, instruction.is_word)
+            }
+            (Op::Neg, None) => {
+                let current = self.read(destination, instruction);
+                self.arithmetic(op, 0, current, instruction.is_word)
+            }
+            _ => return None,
+        };
+        if let Op::Cmp | Op::Test = op {
+            return Some(());
+        }
+        self.write(destination, instruction, result);
+        Some(())
+    }
+
+    fn stack(&mut self, instruction: &Instruction) -> Option<()> {
+        match (instruction.operation, &instruction.destination) {
+            // Like the 8086, push sp pushes the value sp has after the push
+            (Op::Push, Some(location)) => {
+                let sp = self.register(Register::Sp).wrapping_sub(2);
+                self.set_register(Register::Sp, sp);
+                let value = self.read(location, instruction);
+                self.memory
+                    .write_word(self.register(Register::Ss), sp, value);
+            }
+            (Op::Pop, Some(location)) => {
+                let value = self.pop();
+                self.write(location, instruction, value);
+            }
+            (Op::Pushf, None) => self.push(self.flags),
+            (Op::Popf, None) => self.flags = self.pop(),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Calls, jumps and returns
+    fn transfer(&mut self, instruction: &Instruction) -> Option<()> {
+        let op = instruction.operation;
+        if let Op::Ret | Op::Retf = op {
+            self.ip = self.pop();
+            if op == Op::Retf {
+                let cs = self.pop();
+                self.set_register(Register::Cs, cs);
+            }
+            // Also releases that many bytes of arguments
+            if let Some(source) = &instruction.source() {
+                let release = self.read_source(source, instruction)?;
+                let sp = self.register(Register::Sp).wrapping_add(release);
+                self.set_register(Register::Sp, sp);
+            }
+            return Some(());
+        }
+
+        // New cs when the target is in another segment, and the new ip
+        let (segment, offset) = match (&instruction.destination, &instruction.source()) {
+            (None, Some(Source::Jump(jump))) => (None, jump.target as u16),
+            (None, Some(Source::Far(pointer))) => (Some(pointer.segment), pointer.offset),
+            (Some(Location::Addr(eaddr)), None) if matches!(op, Op::CallFar | Op::JmpFar) => {
+                let (segment, offset) = self.effective_address(eaddr, instruction.segment);
+                (
+                    Some(self.memory.read_word(segment, offset.wrapping_add(2))),
+                    self.memory.read_word(segment, offset),
+                )
+            }
+            (Some(location), None) if matches!(op, Op::Call | Op::Jmp) => {
+                (None, self.read(location, instruction))
+            }
+            _ => return None,
+        };
+        if let Op::Call | Op::CallFar = op {
+            if segment.is_some() {
+                self.push(self.register(Register::Cs));
+            }
+            self.push(self.ip);
+        }
+        if let Some(segment) = segment {
+            self.set_register(Register::Cs, segment);
+        }
+        self.ip = offset;
+        Some(())
+    }
+
+    /// Conditional jumps and loops, which count down cx
+    fn branch(&mut self, instruction: &Instruction) -> Option<()> {
+        let Some(Source::Jump(jump)) = instruction.source() else {
+            return None;
+        };
+        let flag = |flag| self.flag(flag);
+        let taken = match instruction.operation {
+            Op::Jo => flag(Flag::Overflow),
+            Op::Jno => !flag(Flag::Overflow),
+            Op::Jb => flag(Flag::Carry),
+            Op::Jnb => !flag(Flag::Carry),
+            Op::Je => flag(Flag::Zero),
+            Op::Jne => !flag(Flag::Zero),
+            Op::Jbe => flag(Flag::Carry) || flag(Flag::Zero),
+            Op::Ja => !flag(Flag::Carry) && !flag(Flag::Zero),
+            Op::Js => flag(Flag::Sign),
+            Op::Jns => !flag(Flag::Sign),
+            Op::Jp => flag(Flag::Parity),
+            Op::Jnp => !flag(Flag::Parity),
+            Op::Jl => flag(Flag::Sign) != flag(Flag::Overflow),
+            Op::Jnl => flag(Flag::Sign) == flag(Flag::Overflow),
+            Op::Jle => flag(Flag::Zero) || flag(Flag::Sign) != flag(Flag::Overflow),
+            Op::Jg => !flag(Flag::Zero) && flag(Flag::Sign) == flag(Flag::Overflow),
+            Op::Jcxz => self.register(Register::Cx) == 0,
+            op => {
+                let zero = flag(Flag::Zero);
+                let cx = self.register(Register::Cx).wrapping_sub(1);
+                self.set_register(Register::Cx, cx);
+                cx != 0
+                    && match op {
+                        Op::Loopz => zero,
+                        Op::Loopnz => !zero,
+                        _ => true,
+                    }
+            }
+        };
+        if taken {
+            self.ip = jump.target as u16;
+        }
+        Some(())
+    }
+
+    fn push(&mut self, value: u16) {
+        let sp = self.register(Register::Sp).wrapping_sub(2);
+        self.set_register(Register::Sp, sp);
+        self.memory
+            .write_word(self.register(Register::Ss), sp, value);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let sp = self.register(Register::Sp);
+        let value = self.memory.read_word(self.register(Register::Ss), sp);
+        self.set_register(Register::Sp, sp.wrapping_add(2));
+        value
+    }
+
+    /// Result of the operation on `a` and `b`, setting the flags the way the 8086 does.
+    /// Inc and dec leave the carry alone, the logical operations clear it along with
+    /// the overflow and auxiliary carry.
+    fn arithmetic(&mut self, op: Op, a: u16, b: u16, is_word: bool) -> u16 {
+        let (mask, sign) = if is_word {
+            (0xffff, 0x8000)
+        } else {
+            (0xff, 0x80)
+        };
+        let (a, b) = (a as u32 & mask, b as u32 & mask);
+        let carry_in = self.flag(Flag::Carry) as u32;
+        let (result, carry, overflow) = match op {
+            Op::Add | Op::Adc | Op::Inc => {
+                let carry_in = if op == Op::Adc { carry_in } else { 0 };
+                let result = a + b + carry_in;
+                (
+                    result,
+                    result > mask,
+                    (a ^ result) & (b ^ result) & sign != 0,
+                )
+            }
+            Op::Sub | Op::Sbb | Op::Cmp | Op::Dec | Op::Neg => {
+                let borrow = if op == Op::Sbb { carry_in } else { 0 };
+                let result = a.wrapping_sub(b).wrapping_sub(borrow);
+                (result, a < b + borrow, (a ^ b) & (a ^ result) & sign != 0)
+            }
+            _ => {
+                let result = match op {
+                    Op::And | Op::Test => a & b,
+                    Op::Or => a | b,
+                    _ => a ^ b,
+                };
+                (result, false, false)
+            }
+        };
+        let is_logical = matches!(op, Op::And | Op::Test | Op::Or | Op::Xor);
+        let result = result & mask;
+
+        if !matches!(op, Op::Inc | Op::Dec) {
+            self.set_flag(Flag::Carry, carry);
+        }
+        // Parity of the low byte only, set when it has an even number of bits set
+        self.set_flag(Flag::Parity, (result as u8).count_ones().is_multiple_of(2));
+        // Carry out of the low nibble
+        self.set_flag(Flag::Auxiliary, !is_logical && (a ^ b ^ result) & 0x10 != 0);
+        self.set_flag(Flag::Zero, result == 0);
+        self.set_flag(Flag::Sign, result & sign != 0);
+        self.set_flag(Flag::Overflow, overflow);
+        result as u16
+    }
+
+    /// Segment and offset of a memory operand. The segment is ss for the addresses
+    /// based on bp and ds for the rest, unless the instruction overrides it.
+    pub fn effective_address(&self, eaddr: &EAddress, segment: Option<Register>) -> (u16, u16) {
+        use Register::*;
+        let (base, displacement) = match *eaddr {
+            EAddress::Direct(addr) => (None, addr),
+            EAddress::Bare(base) => (Some(base), 0),
+            EAddress::WithOffset(base, displacement) => (Some(base), displacement as u16),
+        };
+        let (registers, default): (&[Register], Register) = match base {
+            None => (&[], Ds),
+            Some(Address::BxSi) => (&[Bx, Si], Ds),
+            Some(Address::BxDi) => (&[Bx, Di], Ds),
+            Some(Address::BpSi) => (&[Bp, Si], Ss),
+            Some(Address::BpDi) => (&[Bp, Di], Ss),
+            Some(Address::Si) => (&[Si], Ds),
+            Some(Address::Di) => (&[Di], Ds),
+            Some(Address::Bp) => (&[Bp], Ss),
+            Some(Address::Bx) => (&[Bx], Ds),
+        };
+        let offset = registers.iter().fold(displacement, |offset, &register| {
+            offset.wrapping_add(self.register(register))
+        });
+        (self.register(segment.unwrap_or(default)), offset)
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    fn read_source(&self, source: &Source, instruction: &Instruction) -> Option<u16> {
+        match source {
+            Source::Loc(location) => Some(self.read(location, instruction)),
+            Source::Imm(Immediate::Byte(imm)) => Some(*imm as u16),
+            Source::Imm(Immediate::Word(imm)) => Some(*imm),
+            Source::Jump(_) | Source::Far(_) => None,
+        }
+    }
+
+    /// Memory is accessed with the width and segment override of the instruction
+    fn read(&self, location: &Location, instruction: &Instruction) -> u16 {
+        match location {
+            Location::Reg(register) => self.register(*register),
+            Location::Addr(eaddr) => {
+                let (segment, offset) = self.effective_address(eaddr, instruction.segment);
+                if instruction.is_word {
+                    self.memory.read_word(segment, offset)
+                } else {
+                    self.memory.read_byte(segment, offset) as u16
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, location: &Location, instruction: &Instruction, value: u16) {
+        match location {
+            Location::Reg(register) => self.set_register(*register, value),
+            Location::Addr(eaddr) => {
+                let (segment, offset) = self.effective_address(eaddr, instruction.segment);
+                if instruction.is_word {
+                    self.memory.write_word(segment, offset, value)
+                } else {
+                    self.memory.write_byte(segment, offset, value as u8)
+                }
+            }
+        }
+    }
+}
+
+/// Bits of the flags register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Carry,
+    Parity,
+    Auxiliary,
+    Zero,
+    Sign,
+    Trap,
+    Interrupt,
+    Direction,
+    Overflow,
+}
+impl Flag {
+    /// Every flag, from the lowest bit up
+    pub const ALL: [Flag; 9] = [
+        Flag::Carry,
+        Flag::Parity,
+        Flag::Auxiliary,
+        Flag::Zero,
+        Flag::Sign,
+        Flag::Trap,
+        Flag::Interrupt,
+        Flag::Direction,
+        Flag::Overflow,
+    ];
+
+    /// The bit of the flag in the flags register
+    pub fn mask(self) -> u16 {
+        let bit = match self {
+            Flag::Carry => 0,
+            Flag::Parity => 2,
+            Flag::Auxiliary => 4,
+            Flag::Zero => 6,
+            Flag::Sign => 7,
+            Flag::Trap => 8,
+            Flag::Interrupt => 9,
+            Flag::Direction => 10,
+            Flag::Overflow => 11,
+        };
+        1 << bit
+    }
+}
+
+/// Reason why an instruction couldn't be executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecuteError {
+    /// The simulator doesn't execute the operation, or not with these operands
+    Unsupported { address: usize },
+}
+impl Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::Unsupported { address } => {
+                write!(f, "unsupported instruction at offset {address}")
+            }
+        }
+    }
+}
+impl std::error::Error for ExecuteError {}
+
+/// Why `Cpu::run` stopped executing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// Executed a `hlt`
+    Halt,
+    /// Executed as many instructions as the budget allows
+    Budget,
+    /// cs:ip is outside the loaded image
+    LeftImage,
+    Decode(DecodeError),
+    Execute(ExecuteError),
+}
+impl Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Halt => write!(f, "halted"),
+            StopReason::Budget => write!(f, "ran out of instruction budget"),
+            StopReason::LeftImage => write!(f, "left the loaded image"),
+            StopReason::Decode(err) => write!(f, "{err}"),
+            StopReason::Execute(err) => write!(f, "{err}"),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use crate::table::{layouts, Field, Layout};
+use crate::{Address, EAddress, Immediate, Instruction, Location, Register, Repeat, Source};
+
+impl Instruction {
+    /// Machine code of the instruction, the inverse of decoding it.
+    ///
+    /// Of the encodings that fit the operands the shortest is picked, preferring the same
+    /// forms as nasm, so encoding a decoded instruction gives back canonical machine code unchanged.
+    ///
+    /// # Panics
+    ///
+    /// When no encoding fits the operands, which can't happen to a decoded instruction.
+    pub fn encode(&self) -> Vec<u8> {
+        self.try_encode()
+            .unwrap_or_else(|| panic!("no encoding of {self:?}"))
+    }
+
+    /// Same as `encode`, but `None` when no encoding fits the operands
+    pub(crate) fn try_encode(&self) -> Option<Vec<u8>> {
+        layouts()
+            .iter()
+            .filter(|layout| layout.op == self.operation)
+            .filter_map(|layout| encode_as(self, layout))
+            .min_by_key(Vec::len)
+    }
+}
+
+/// Encodes the instruction in the layout of one encoding, if its operands fit
+fn encode_as(instruction: &Instruction, layout: &Layout) -> Option<Vec<u8>> {
+    let mut bits = layout.value;
+    // Displacement and data following the opcode and ModRM byte
+    let mut tail = Vec::new();
+
+    let is_word = match layout.w.value() {
+        Some(w) => w == 1,
+        None => {
+            bits |= layout.w.insert(instruction.is_word as u8)?;
+            instruction.is_word
+        }
+    };
+    let destination = instruction.destination.as_ref();
+    let source = instruction.source();
+    let source = source.as_ref();
+    // Operands of the reg and r/m fields, and whatever is left for the rest of the encoding
+    let (reg, rm, rest) = match (layout.reg, layout.location) {
+        (Some(_), Some(_)) => {
+            let (Some(destination), Some(Source::Loc(source))) = (destination, source) else {
+                return None;
+            };
+            // Like nasm, a register source goes in the reg field
+            let d = match layout.d.value() {
+                Some(d) => d == 1,
+                None => !matches!(source, Location::Reg(_)),
+            };
+            bits |= layout.d.insert(d as u8)?;
+            if d {
+                (Some(destination), Some(source), None)
+            } else {
+                (Some(source), Some(destination), None)
+            }
+        }
+        (Some(_), None) => (Some(destination?), None, source),
+        (None, Some(_)) => (None, Some(destination?), source),
+        (None, None) if destination.is_some() => return None,
+        (None, None) => (None, None, source),
+    };
+
+    if let (Some(field), Some(reg)) = (layout.reg, reg) {
+        let Location::Reg(register) = reg else {
+            return None;
+        };
+        if layout.sr && Register::segment(register.value()) != *register {
+            return None;
+        }
+        if !layout.sr && !fits_width(*register, is_word) {
+            return None;
+        }
+        bits |= field.insert(register.value())?;
+    }
+
+    match (layout.location, rm) {
+        (Some(location @ (Field::Rm | Field::RmMem)), Some(rm)) => {
+            let (mode, rm) = match rm {
+                Location::Reg(register) if matches!(location, Field::Rm) => {
+                    if !fits_width(*register, is_word) {
+                        return None;
+                    }
+                    (0b11, register.value())
+                }
+                Location::Reg(_) => return None,
+                Location::Addr(eaddr) => encode_address(eaddr, &mut tail),
+            };
+            bits |= layout.mode.insert(mode)? | layout.rm.insert(rm)?;
+        }
+        (Some(Field::Addr), Some(Location::Addr(EAddress::Direct(addr)))) => {
+            tail.extend(addr.to_le_bytes())
+        }
+        (Some(Field::ImpRm(register)), Some(Location::Reg(rm))) if register == *rm => {}
+        (None, None) => {}
+        _ => return None,
+    }
+
+    let mut relative = None;
+    match (layout.source, rest) {
+        (None, None) => {}
+        (Some(Field::Data), Some(Source::Imm(imm))) => {
+            let data = immediate_value(imm);
+            // Byte of data sign extended to a word, when it fits
+            let sign_extend =
+                is_word && (data as i16) == (data as i8 as i16) && layout.s.insert(1).is_some();
+            bits |= layout.s.insert(sign_extend as u8)?;
+            if is_word && !sign_extend {
+                tail.extend(data.to_le_bytes());
+            } else {
+                tail.push(
+                    u8::try_from(data)
+                        .ok()
+                        .or(sign_extend.then_some(data as u8))?,
+                );
+            }
+        }
+        (Some(Field::DataByte), Some(Source::Imm(imm))) => {
+            tail.push(u8::try_from(immediate_value(imm)).ok()?)
+        }
+        // A byte would need sign extending, which the field can't tell apart from zero extending
+        (Some(Field::DataWord), Some(Source::Imm(Immediate::Word(imm)))) => {
+            tail.extend(imm.to_le_bytes())
+        }
+        (Some(Field::Base), None) => tail.push(10),
+        (Some(Field::Base), Some(Source::Imm(imm))) => {
+            tail.push(u8::try_from(immediate_value(imm)).ok()?)
+        }
+        (Some(Field::V), Some(Source::Imm(imm))) if immediate_value(imm) == 1 => {
+            bits |= layout.v.insert(0)?
+        }
+        (Some(Field::V), Some(Source::Loc(Location::Reg(Register::Cl)))) => {
+            bits |= layout.v.insert(1)?
+        }
+        // The displacement is as wide as the instruction, so a near jump stays one when a
+        // byte would do
+        (Some(field @ (Field::Rel8 | Field::Rel16)), Some(Source::Jump(jump)))
+            if matches!(field, Field::Rel16) == instruction.is_word =>
+        {
+            relative = Some((field, jump.target))
+        }
+        (Some(Field::FarPointer), Some(Source::Far(pointer))) => {
+            tail.extend(pointer.offset.to_le_bytes());
+            tail.extend(pointer.segment.to_le_bytes());
+        }
+        (Some(Field::Esc), Some(Source::Imm(imm))) => {
+            let opcode = u8::try_from(immediate_value(imm)).ok()?;
+            let [high, low] = layout.esc;
+            bits |= high.insert(opcode >> 3)? | low.insert(opcode & 0b111)?;
+        }
+        _ => return None,
+    }
+
+    let mut bytes = prefixes(instruction);
+    let [opcode, modrm] = bits.to_be_bytes();
+    bytes.push(opcode);
+    if layout.modrm {
+        bytes.push(modrm);
+    }
+    bytes.extend(tail);
+    // The displacement is relative to the end of the instruction, which it is the last part of
+    match relative {
+        Some((Field::Rel8, target)) => {
+            let next = instruction.address + bytes.len() + 1;
+            let displacement = i8::try_from(target.wrapping_sub(next) as isize).ok()?;
+            bytes.push(displacement as u8);
+        }
+        Some((_, target)) => {
+            let next = instruction.address + bytes.len() + 2;
+            let displacement = target.wrapping_sub(next) as u16;
+            bytes.extend(displacement.to_le_bytes());
+        }
+        None => {}
+    }
+    Some(bytes)
+}
+
+/// Prefix bytes in the order nasm emits them
+fn prefixes(instruction: &Instruction) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match instruction.repeat {
+        Some(Repeat::Rep) => bytes.push(0xf3),
+        Some(Repeat::Repne) => bytes.push(0xf2),
+        None => {}
+    }
+    if instruction.lock {
+        bytes.push(0xf0);
+    }
+    if let Some(segment) = instruction.segment {
+        bytes.push(0b0010_0110 | (segment.value() << 3));
+    }
+    bytes
+}
+
+/// `mod` and `r/m` of a memory operand, with its displacement added to `tail`
+fn encode_address(eaddr: &EAddress, tail: &mut Vec<u8>) -> (u8, u8) {
+    match *eaddr {
+        EAddress::Direct(addr) => {
+            tail.extend(addr.to_le_bytes());
+            (0b00, 0b110)
+        }
+        // Its encoding is taken by the direct address, so [bp] needs a zero displacement
+        EAddress::Bare(Address::Bp) => {
+            tail.push(0);
+            (0b01, 0b110)
+        }
+        EAddress::Bare(addr) => (0b00, address_value(addr)),
+        EAddress::WithOffset(addr, offset) => match i8::try_from(offset) {
+            Ok(offset) => {
+                tail.push(offset as u8);
+                (0b01, address_value(addr))
+            }
+            Err(_) => {
+                tail.extend(offset.to_le_bytes());
+                (0b10, address_value(addr))
+            }
+        },
+    }
+}
+
+fn address_value(addr: Address) -> u8 {
+    match addr {
+        Address::BxSi => 0b000,
+        Address::BxDi => 0b001,
+        Address::BpSi => 0b010,
+        Address::BpDi => 0b011,
+        Address::Si => 0b100,
+        Address::Di => 0b101,
+        Address::Bp => 0b110,
+        Address::Bx => 0b111,
+    }
+}
+
+/// Whether a general purpose register has the operand width
+fn fits_width(register: Register, is_word: bool) -> bool {
+    let value = register.value();
+    if is_word {
+        Register::word(value) == register
+    } else {
+        Register::byte(value) == register
+    }
+}
+
+fn immediate_value(imm: &Immediate) -> u16 {
+    match *imm {
+        Immediate::Byte(imm) => imm as u16,
+        Immediate::Word(imm) => imm,
+    }
+}
@@ -1,52 +1,313 @@
+mod assembler;
+mod cpu;
+mod encoder;
+mod memory;
 mod parser;
+mod table;
 #[cfg(test)]
 mod tests;
+mod trace;
 
+pub use crate::assembler::{assemble, AssembleError};
+pub use crate::cpu::{Cpu, ExecuteError, Flag, StopReason};
+pub use crate::memory::Memory;
 use crate::parser::parse_instruction;
+pub use crate::trace::trace;
 use core::fmt;
-use std::fmt::Display;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
 
+/// Disassembles the whole program into nasm compatible assembly.
+///
+/// Bytes that can't be decoded are emitted as `db` lines, with the reason as a comment,
+/// and decoding resumes at the following byte.
+/// Jump targets that land on the start of a line get a `label_N:` line and are referred to by name.
 pub fn disassemble(program: &[u8]) -> String {
-    let mut instruction_stream = (program, 0);
-    let mut asm = format!("bits 16\n\n");
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < program.len() {
+        let line = decode_instruction(program, offset);
+        offset += line.as_ref().map_or(1, Instruction::size);
+        lines.push(line);
+    }
 
-    while !instruction_stream.0.is_empty() {
-        let (tail, instruction) = parse_instruction(instruction_stream).unwrap();
-        // println!("{instruction:?}");
-        asm.push_str(&format!("{instruction}\n"));
+    let starts: BTreeSet<usize> = lines
+        .iter()
+        .map(|line| match line {
+            Ok(instruction) => instruction.address(),
+            Err(err) => err.offset(),
+        })
+        .collect();
+    let labels: Labels = lines
+        .iter()
+        .filter_map(|line| line.as_ref().ok()?.jump_target())
+        .filter(|target| starts.contains(target))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .enumerate()
+        .map(|(n, target)| (target, format!("label_{n}")))
+        .collect();
 
-        instruction_stream = tail;
+    let mut asm = "bits 16\n\n".to_string();
+    for line in lines {
+        match line {
+            Ok(instruction) => {
+                if let Some(label) = labels.get(&instruction.address()) {
+                    asm.push_str(&format!("{label}:\n"));
+                }
+                asm.push_str(&format!("{}\n", instruction.with_labels(&labels)));
+            }
+            Err(err) => {
+                if let Some(label) = labels.get(&err.offset()) {
+                    asm.push_str(&format!("{label}:\n"));
+                }
+                let byte = program[err.offset()];
+                asm.push_str(&format!("db 0x{byte:02x} ; {err}\n"));
+            }
+        }
     }
     asm
 }
 
+/// Label names of jump targets, keyed by their address
+type Labels = BTreeMap<usize, String>;
+
+/// Decodes the single instruction starting at `offset` into the program
+#[inline]
+pub fn decode_instruction(program: &[u8], offset: usize) -> Result<Instruction, DecodeError> {
+    decode(&program[offset..], offset).map(|(instruction, _)| instruction)
+}
+
+/// Decodes the instruction at the start of `bytes`, along with the number of bytes it takes up.
+///
+/// `address` is where `bytes` starts in the program, jump targets and error offsets are relative
+/// to the program rather than to `bytes`.
+#[inline]
+pub fn decode(bytes: &[u8], address: usize) -> Result<(Instruction, usize), DecodeError> {
+    parse_instruction(bytes, address).map_err(|err| err.at(address))
+}
+
+/// Reason why an instruction couldn't be decoded.
+///
+/// `offset` is the byte offset of the start of the offending instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOpcode { offset: usize, opcode: u8 },
+    Truncated { offset: usize },
+    InvalidModRm { offset: usize, modrm: u8 },
+}
+impl DecodeError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            DecodeError::UnknownOpcode { offset, .. }
+            | DecodeError::Truncated { offset }
+            | DecodeError::InvalidModRm { offset, .. } => offset,
+        }
+    }
+
+    /// The parser only sees the bytes of a single instruction,
+    /// so the offset into the program gets filled in by the caller.
+    fn at(self, offset: usize) -> Self {
+        match self {
+            DecodeError::UnknownOpcode { opcode, .. } => {
+                DecodeError::UnknownOpcode { offset, opcode }
+            }
+            DecodeError::Truncated { .. } => DecodeError::Truncated { offset },
+            DecodeError::InvalidModRm { modrm, .. } => DecodeError::InvalidModRm { offset, modrm },
+        }
+    }
+}
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode { offset, opcode } => {
+                write!(f, "unknown opcode 0x{opcode:02x} at offset {offset}")
+            }
+            DecodeError::Truncated { offset } => {
+                write!(f, "truncated instruction at offset {offset}")
+            }
+            DecodeError::InvalidModRm { offset, modrm } => {
+                write!(f, "invalid ModRM byte 0x{modrm:02x} at offset {offset}")
+            }
+        }
+    }
+}
+impl std::error::Error for DecodeError {}
+
 pub struct Instruction {
-    _address: usize,
-    _size: usize,
+    address: usize,
+    size: usize,
+    /// Width of the operands, the only hint for a lone memory operand
+    is_word: bool,
+    lock: bool,
+    /// Segment override prefix
+    segment: Option<Register>,
+    repeat: Option<Repeat>,
     operation: Op,
-    destination: Location,
-    source: Source,
+    destination: Option<Location>,
+    source: SourceParts,
+}
+impl Instruction {
+    /// Offset of the first byte of the instruction
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    /// Length of the encoded instruction in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Address a branch can continue at, other than the next instruction
+    pub fn jump_target(&self) -> Option<usize> {
+        match self.source() {
+            Some(Source::Jump(jump)) => Some(jump.target),
+            _ => None,
+        }
+    }
+
+    fn source(&self) -> Option<Source> {
+        self.source.source()
+    }
+
+    fn with_labels<'a>(&'a self, labels: &'a Labels) -> Labelled<'a> {
+        Labelled {
+            instruction: self,
+            labels,
+        }
+    }
+
+    /// The bytes the instruction was decoded from, `program` being the input it was decoded from
+    pub fn raw_bytes<'a>(&self, program: &'a [u8]) -> &'a [u8] {
+        &program[self.address..self.address + self.size]
+    }
 }
 impl fmt::Debug for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
             "{:?} {:?}, {:?}",
-            self.operation, self.destination, self.source
+            self.operation,
+            self.destination,
+            self.source()
         )
     }
 }
 impl Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{} {}, {}",
-            self.operation, self.destination, self.source
-        )
+        self.with_labels(&Labels::new()).fmt(f)
+    }
+}
+
+/// Displays an instruction with its jump target replaced by a label, when it has one
+struct Labelled<'a> {
+    instruction: &'a Instruction,
+    labels: &'a Labels,
+}
+impl Display for Labelled<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Labelled {
+            instruction,
+            labels,
+        } = self;
+        let source = instruction.source();
+        // The override is shown on the memory operand, or as a prefix when there is none
+        let segment = instruction.segment.map(|segment| format!("{segment}:"));
+        let location = |location: &Location| match (location, &segment) {
+            (Location::Addr(_), Some(segment)) => format!("{segment}{location}"),
+            _ => location.to_string(),
+        };
+        let has_memory_operand = matches!(instruction.destination, Some(Location::Addr(_)))
+            || matches!(source, Some(Source::Loc(Location::Addr(_))));
+        if let (Some(segment), false) = (instruction.segment, has_memory_operand) {
+            write!(f, "{segment} ")?;
+        }
+        if instruction.lock {
+            write!(f, "lock ")?;
+        }
+        if let Some(repeat) = instruction.repeat {
+            let prefix = match (repeat, instruction.operation) {
+                (Repeat::Rep, Op::Cmpsb | Op::Cmpsw | Op::Scasb | Op::Scasw) => "repe",
+                (Repeat::Rep, _) => "rep",
+                (Repeat::Repne, _) => "repne",
+            };
+            write!(f, "{prefix} ")?;
+        }
+
+        // Neither operand tells the assembler how wide a memory access is,
+        // the size goes on the immediate where there is one
+        let size = if instruction.is_word { "word" } else { "byte" };
+        let is_shift = instruction.operation.is_shift();
+        let memory_destination = matches!(instruction.destination, Some(Location::Addr(_)));
+        let sized_source =
+            memory_destination && !is_shift && matches!(source, Some(Source::Imm(_)));
+        let is_far = matches!(instruction.operation, Op::CallFar | Op::JmpFar);
+        let sized_destination =
+            memory_destination && !sized_source && !is_far && (is_shift || source.is_none());
+
+        // The operands of these are the other way around in the syntax
+        if let (Op::Out | Op::Esc, Some(destination), Some(source)) =
+            (instruction.operation, &instruction.destination, &source)
+        {
+            let source = match source {
+                Source::Loc(source) => location(source),
+                source => source.to_string(),
+            };
+            let destination = location(destination);
+            return write!(f, "{} {source}, {destination}", instruction.operation);
+        }
+
+        write!(f, "{}", instruction.operation)?;
+        if let Some(destination) = &instruction.destination {
+            if sized_destination {
+                write!(f, " {size}")?;
+            }
+            write!(f, " {}", location(destination))?;
+        }
+        let Some(source) = &source else {
+            return Ok(());
+        };
+        let separator = if instruction.destination.is_some() {
+            ", "
+        } else {
+            " "
+        };
+        write!(f, "{separator}")?;
+        match (&instruction.destination, source) {
+            (_, Source::Imm(imm)) if sized_source => write!(f, "{size} {imm}"),
+            (_, Source::Jump(jump)) => {
+                // Otherwise the short form would be assembled wherever it reaches, its
+                // displacement being one more since it is a byte shorter
+                let reaches_short = (-129..=126).contains(&jump.displacement);
+                if instruction.operation == Op::Jmp && instruction.is_word && reaches_short {
+                    write!(f, "near ")?;
+                }
+                match labels.get(&jump.target) {
+                    Some(label) => write!(f, "{label}"),
+                    // Relative to the start of this instruction, so it reassembles at any address
+                    None => {
+                        let relative = jump.target.wrapping_sub(instruction.address) as isize;
+                        write!(f, "${relative:+}")
+                    }
+                }
+            }
+            (_, Source::Loc(source)) => write!(f, "{}", location(source)),
+            _ => write!(f, "{source}"),
+        }
     }
 }
 
-#[derive(Debug)]
+/// Repeat prefix of the string instructions.
+/// Comparisons stop early on the zero flag, `Rep` repeats while equal and `Repne` while not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Rep,
+    Repne,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum Location {
     Reg(Register),
     Addr(EAddress),
@@ -57,27 +318,129 @@ impl Display for Location {
             Location::Reg(reg) => write!(f, "{reg}"),
             Location::Addr(eaddr) => match eaddr {
                 EAddress::Bare(addr) => write!(f, "[{addr}]"),
+                EAddress::WithOffset(addr, 0) => write!(f, "[{addr}]"),
+                EAddress::WithOffset(addr, offset) if *offset < 0 => {
+                    write!(f, "[{addr} - {}]", offset.unsigned_abs())
+                }
                 EAddress::WithOffset(addr, offset) => write!(f, "[{addr} + {offset}]"),
+                EAddress::Direct(addr) => write!(f, "[{addr}]"),
             },
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Source {
     Loc(Location),
     Imm(Immediate),
+    Jump(Jump),
+    Far(FarPointer),
 }
 impl Display for Source {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Source::Loc(loc) => write!(f, "{}", loc),
             Source::Imm(imm) => write!(f, "{imm}"),
+            Source::Jump(jump) => write!(f, "{}", jump.target),
+            Source::Far(pointer) => write!(f, "{}:{}", pointer.segment, pointer.offset),
         }
     }
 }
 
-#[derive(Debug)]
+/// What a `Source` is made of, every part filled in whatever sort of source it is, so decoding
+/// can pick the sort without branching on it
+#[derive(Debug, Clone, Copy)]
+struct SourceParts {
+    kind: SourceKind,
+    /// Operand of a `Loc`, with none there is no source
+    location: Option<Location>,
+    /// Value of an immediate, displacement of a jump or offset of a far pointer
+    value: u16,
+    /// The immediate is a word
+    is_word: bool,
+    /// Segment of a far pointer
+    segment: u16,
+    /// Target of a jump
+    target: usize,
+}
+impl SourceParts {
+    fn source(&self) -> Option<Source> {
+        let value = self.value;
+        Some(match self.kind {
+            SourceKind::Loc => Source::Loc(self.location?),
+            SourceKind::Imm if self.is_word => Source::Imm(Immediate::Word(value)),
+            SourceKind::Imm => Source::Imm(Immediate::Byte(value as u8)),
+            SourceKind::Jump => Source::Jump(Jump {
+                displacement: value as i16,
+                target: self.target,
+            }),
+            SourceKind::Far => Source::Far(FarPointer {
+                segment: self.segment,
+                offset: value,
+            }),
+        })
+    }
+}
+impl From<Option<Source>> for SourceParts {
+    fn from(source: Option<Source>) -> Self {
+        let mut parts = SourceParts {
+            kind: SourceKind::Loc,
+            location: None,
+            value: 0,
+            is_word: false,
+            segment: 0,
+            target: 0,
+        };
+        match source {
+            None => {}
+            Some(Source::Loc(location)) => parts.location = Some(location),
+            Some(Source::Imm(immediate)) => {
+                parts.kind = SourceKind::Imm;
+                (parts.value, parts.is_word) = match immediate {
+                    Immediate::Byte(value) => (value as u16, false),
+                    Immediate::Word(value) => (value, true),
+                };
+            }
+            Some(Source::Jump(jump)) => {
+                parts.kind = SourceKind::Jump;
+                parts.value = jump.displacement as u16;
+                parts.target = jump.target;
+            }
+            Some(Source::Far(pointer)) => {
+                parts.kind = SourceKind::Far;
+                parts.value = pointer.offset;
+                parts.segment = pointer.segment;
+            }
+        }
+        parts
+    }
+}
+
+/// Sort of `Source` that `SourceParts` stand for
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) enum SourceKind {
+    #[default]
+    Loc,
+    Imm,
+    Jump,
+    Far,
+}
+
+/// Absolute `segment:offset` address
+#[derive(Debug, Clone, Copy)]
+pub struct FarPointer {
+    pub segment: u16,
+    pub offset: u16,
+}
+
+/// Relative branch, `displacement` is counted from the end of the instruction
+#[derive(Debug, Clone, Copy)]
+pub struct Jump {
+    pub displacement: i16,
+    pub target: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum Immediate {
     Byte(u8),
     Word(u16),
@@ -91,27 +454,225 @@ impl Display for Immediate {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Op {
-    MovRegRM,
-    MovImmediateRM,
-    MovImmediateReg,
-    Unimplemented,
+    Mov,
+    Add,
+    Or,
+    Adc,
+    Sbb,
+    And,
+    Sub,
+    Xor,
+    Cmp,
+    Jo,
+    Jno,
+    Jb,
+    Jnb,
+    Je,
+    Jne,
+    Jbe,
+    Ja,
+    Js,
+    Jns,
+    Jp,
+    Jnp,
+    Jl,
+    Jnl,
+    Jle,
+    Jg,
+    Loopnz,
+    Loopz,
+    Loop,
+    Jcxz,
+    Push,
+    Pop,
+    Movsb,
+    Movsw,
+    Cmpsb,
+    Cmpsw,
+    Scasb,
+    Scasw,
+    Lodsb,
+    Lodsw,
+    Stosb,
+    Stosw,
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+    Shl,
+    Shr,
+    Sar,
+    Test,
+    Not,
+    Neg,
+    Mul,
+    Imul,
+    Div,
+    Idiv,
+    Pushf,
+    Popf,
+    Call,
+    CallFar,
+    Jmp,
+    JmpFar,
+    Ret,
+    Retf,
+    Inc,
+    Dec,
+    Lea,
+    Lds,
+    Les,
+    Xchg,
+    Nop,
+    In,
+    Out,
+    Xlat,
+    Lahf,
+    Sahf,
+    Int,
+    Int3,
+    Into,
+    Iret,
+    Clc,
+    Stc,
+    Cmc,
+    Cld,
+    Std,
+    Cli,
+    Sti,
+    Daa,
+    Das,
+    Aaa,
+    Aas,
+    Aam,
+    Aad,
+    Cbw,
+    Cwd,
+    Hlt,
+    Wait,
+    Esc,
+}
+impl Op {
+    fn is_shift(self) -> bool {
+        matches!(
+            self,
+            Op::Rol | Op::Ror | Op::Rcl | Op::Rcr | Op::Shl | Op::Shr | Op::Sar
+        )
+    }
 }
 impl Display for Op {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let opcode_string = match self {
-            Op::MovRegRM | Op::MovImmediateRM | Op::MovImmediateReg => "mov",
-            _ => "unimplemented!",
+            Op::Mov => "mov",
+            Op::Add => "add",
+            Op::Or => "or",
+            Op::Adc => "adc",
+            Op::Sbb => "sbb",
+            Op::And => "and",
+            Op::Sub => "sub",
+            Op::Xor => "xor",
+            Op::Cmp => "cmp",
+            Op::Jo => "jo",
+            Op::Jno => "jno",
+            Op::Jb => "jb",
+            Op::Jnb => "jnb",
+            Op::Je => "je",
+            Op::Jne => "jne",
+            Op::Jbe => "jbe",
+            Op::Ja => "ja",
+            Op::Js => "js",
+            Op::Jns => "jns",
+            Op::Jp => "jp",
+            Op::Jnp => "jnp",
+            Op::Jl => "jl",
+            Op::Jnl => "jnl",
+            Op::Jle => "jle",
+            Op::Jg => "jg",
+            Op::Loopnz => "loopnz",
+            Op::Loopz => "loopz",
+            Op::Loop => "loop",
+            Op::Jcxz => "jcxz",
+            Op::Push => "push",
+            Op::Pop => "pop",
+            Op::Movsb => "movsb",
+            Op::Movsw => "movsw",
+            Op::Cmpsb => "cmpsb",
+            Op::Cmpsw => "cmpsw",
+            Op::Scasb => "scasb",
+            Op::Scasw => "scasw",
+            Op::Lodsb => "lodsb",
+            Op::Lodsw => "lodsw",
+            Op::Stosb => "stosb",
+            Op::Stosw => "stosw",
+            Op::Rol => "rol",
+            Op::Ror => "ror",
+            Op::Rcl => "rcl",
+            Op::Rcr => "rcr",
+            Op::Shl => "shl",
+            Op::Shr => "shr",
+            Op::Sar => "sar",
+            Op::Test => "test",
+            Op::Not => "not",
+            Op::Neg => "neg",
+            Op::Mul => "mul",
+            Op::Imul => "imul",
+            Op::Div => "div",
+            Op::Idiv => "idiv",
+            Op::Pushf => "pushf",
+            Op::Popf => "popf",
+            Op::Call => "call",
+            // Only used for the indirect forms, a pointer operand is far already
+            Op::CallFar => "call far",
+            Op::Jmp => "jmp",
+            Op::JmpFar => "jmp far",
+            Op::Ret => "ret",
+            Op::Retf => "retf",
+            Op::Inc => "inc",
+            Op::Dec => "dec",
+            Op::Lea => "lea",
+            Op::Lds => "lds",
+            Op::Les => "les",
+            Op::Xchg => "xchg",
+            Op::Nop => "nop",
+            Op::In => "in",
+            Op::Out => "out",
+            Op::Xlat => "xlat",
+            Op::Lahf => "lahf",
+            Op::Sahf => "sahf",
+            Op::Int => "int",
+            Op::Int3 => "int3",
+            Op::Into => "into",
+            Op::Iret => "iret",
+            Op::Clc => "clc",
+            Op::Stc => "stc",
+            Op::Cmc => "cmc",
+            Op::Cld => "cld",
+            Op::Std => "std",
+            Op::Cli => "cli",
+            Op::Sti => "sti",
+            Op::Daa => "daa",
+            Op::Das => "das",
+            Op::Aaa => "aaa",
+            Op::Aas => "aas",
+            Op::Aam => "aam",
+            Op::Aad => "aad",
+            Op::Cbw => "cbw",
+            Op::Cwd => "cwd",
+            Op::Hlt => "hlt",
+            Op::Wait => "wait",
+            Op::Esc => "esc",
         };
         write!(f, "{}", opcode_string)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum EAddress {
     Bare(Address),
-    WithOffset(Address, Immediate),
+    WithOffset(Address, i16),
+    Direct(u16),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -142,7 +703,7 @@ impl Display for Address {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Register {
     Al,
     Dl,
@@ -160,6 +721,10 @@ pub enum Register {
     Bp,
     Si,
     Di,
+    Es,
+    Cs,
+    Ss,
+    Ds,
 }
 impl Register {
     fn byte(value: u8) -> Self {
@@ -186,6 +751,37 @@ impl Register {
             _ => Self::Di,
         }
     }
+    /// Same as `byte` or `word`, looked up rather than matched since the decoder would otherwise
+    /// branch on what are as good as random bits to it
+    fn sized(value: u8, is_word: bool) -> Self {
+        use Register::*;
+        static REGISTERS: [[Register; 8]; 2] = [
+            [Al, Cl, Dl, Bl, Ah, Ch, Dh, Bh],
+            [Ax, Cx, Dx, Bx, Sp, Bp, Si, Di],
+        ];
+        REGISTERS[is_word as usize][value as usize & 0b111]
+    }
+    fn segment(value: u8) -> Self {
+        match value {
+            0b00 => Self::Es,
+            0b01 => Self::Cs,
+            0b10 => Self::Ss,
+            _ => Self::Ds,
+        }
+    }
+    /// Inverse of `byte`, `word` and `segment`
+    fn value(self) -> u8 {
+        match self {
+            Self::Al | Self::Ax | Self::Es => 0b000,
+            Self::Cl | Self::Cx | Self::Cs => 0b001,
+            Self::Dl | Self::Dx | Self::Ss => 0b010,
+            Self::Bl | Self::Bx | Self::Ds => 0b011,
+            Self::Ah | Self::Sp => 0b100,
+            Self::Ch | Self::Bp => 0b101,
+            Self::Dh | Self::Si => 0b110,
+            Self::Bh | Self::Di => 0b111,
+        }
+    }
 }
 impl Display for Register {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -206,6 +802,10 @@ impl Display for Register {
             Register::Bp => "bp",
             Register::Si => "si",
             Register::Di => "di",
+            Register::Es => "es",
+            Register::Cs => "cs",
+            Register::Ss => "ss",
+            Register::Ds => "ds",
         };
         write!(f, "{}", register_str)
     }
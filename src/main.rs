@@ -1,18 +1,39 @@
-use sim86::disassemble;
+use sim86::{disassemble, trace, Cpu, StopReason};
 use std::{env, fs};
 
+/// Instructions a program may execute before it's taken to be stuck
+const BUDGET: usize = 1_000_000;
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let (name, files) = args.split_first().unwrap();
+    let (name, args) = args.split_first().unwrap();
+    let (execute, files) = match args.split_first() {
+        Some((flag, files)) if flag == "--exec" => (true, files),
+        _ => (false, args),
+    };
 
     if files.is_empty() {
         eprintln!("\nAt least one 8086 binary file expected");
-        eprintln!("USAGE: {} [8086 machine code file] \n", name);
+        eprintln!("USAGE: {} [--exec] [8086 machine code file] \n", name);
         return;
     }
     for path in files {
         let program = fs::read(path).expect("filepath exists");
 
+        if execute {
+            println!("--- {} execution ---", path);
+
+            let mut cpu = Cpu::default();
+            cpu.load(&program);
+            let (text, stop) = trace(&mut cpu, BUDGET);
+            print!("{}", text);
+            if !matches!(stop, StopReason::Halt | StopReason::LeftImage) {
+                eprintln!("{}: {}", path, stop);
+            }
+            println!();
+            continue;
+        }
+
         println!(";;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;");
         println!(";;; disassembly of {}\n", path);
 
@@ -0,0 +1,52 @@
+use core::fmt;
+
+/// The 1 MiB the 8086 can address, through a segment and an offset into it
+#[derive(Clone, PartialEq, Eq)]
+pub struct Memory {
+    bytes: Box<[u8]>,
+}
+
+impl Memory {
+    pub const SIZE: usize = 1 << 20;
+
+    /// `segment * 16 + offset`, which wraps around at 1 MiB like it does on the 8086
+    pub fn physical(segment: u16, offset: u16) -> usize {
+        ((segment as usize) << 4).wrapping_add(offset as usize) & (Self::SIZE - 1)
+    }
+
+    pub fn read_byte(&self, segment: u16, offset: u16) -> u8 {
+        self.bytes[Self::physical(segment, offset)]
+    }
+
+    /// Little endian, the high byte of a word at offset 0xffff is at offset 0 of the same segment
+    pub fn read_word(&self, segment: u16, offset: u16) -> u16 {
+        let low = self.read_byte(segment, offset);
+        let high = self.read_byte(segment, offset.wrapping_add(1));
+        u16::from_le_bytes([low, high])
+    }
+
+    pub fn write_byte(&mut self, segment: u16, offset: u16, value: u8) {
+        self.bytes[Self::physical(segment, offset)] = value;
+    }
+
+    /// Little endian, wrapping within the segment the same as `read_word`
+    pub fn write_word(&mut self, segment: u16, offset: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write_byte(segment, offset, low);
+        self.write_byte(segment, offset.wrapping_add(1), high);
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory {
+            bytes: vec![0; Self::SIZE].into_boxed_slice(),
+        }
+    }
+}
+
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Memory").finish_non_exhaustive()
+    }
+}
@@ -1,140 +1,175 @@
-use crate::{Address, EAddress, Immediate, Instruction, Location, Op, Register, Source};
+use std::hint::select_unpredictable;
 
-use nom::{
-    bits::complete::{bool, take},
-    combinator::map,
-    IResult, InputIter, InputLength, Slice,
+use crate::table::{decoding, is_known};
+use crate::{
+    Address, DecodeError, EAddress, Instruction, Location, Register, Repeat, SourceKind,
+    SourceParts,
 };
 
-pub type BitInput<'a> = (&'a [u8], usize);
+/// Decodes the instruction at the start of `bytes`, along with the number of bytes it takes up
+#[inline]
+pub fn parse_instruction(
+    bytes: &[u8],
+    address: usize,
+) -> Result<(Instruction, usize), DecodeError> {
+    let (prefix_len, prefixes) = parse_prefixes(bytes);
+    let bytes = &bytes[prefix_len..];
+    let opcode = *bytes.first().ok_or(DecodeError::Truncated { offset: 0 })?;
+    // A missing ModRM byte reads as zero, which is valid for every group
+    // and leaves it to the reader to find the instruction truncated
+    let modrm = bytes.get(1).copied().unwrap_or_default();
+    let (size, decoding) = decoding(opcode, modrm);
+    if decoding.bad_modes >> (modrm >> 6) & 1 == 1 {
+        return Err(if is_known(opcode) {
+            DecodeError::InvalidModRm { offset: 0, modrm }
+        } else {
+            DecodeError::UnknownOpcode { offset: 0, opcode }
+        });
+    }
+    let reader = Reader::new(bytes);
 
-pub fn parse_instruction(i: BitInput) -> IResult<BitInput, Instruction> {
-    use nom::sequence::tuple;
-    let (i, opcode) = parse_opcode(i)?;
-    let (i, destination, source) = match opcode {
-        Op::MovRegRM => {
-            let (i, (d_bit, is_word, mode)) = tuple((bool, bool, take_2bits))(i)?;
-            let (i, reg) = parse_reg(is_word)(i)?;
-            let (i, rm) = parse_rm(mode, is_word, i)?;
-            if d_bit {
-                (i, Location::Reg(reg), Source::Loc(rm))
-            } else {
-                (i, rm, Source::Loc(Location::Reg(reg)))
-            }
-        }
-        Op::MovImmediateReg => {
-            let (i, is_word) = bool(i)?;
-            let (i, reg) = parse_reg(is_word)(i)?;
-            let (i, val) = parse_immediate(i, is_word)?;
-            (i, Location::Reg(reg), Source::Imm(val))
-        }
-        Op::MovImmediateRM => todo!(),
-        Op::Unimplemented => todo!(),
+    let is_word = decoding.is_word;
+    // Counted from the ModRM byte as it is, which doesn't have to wait on the decoding
+    let displacement = DISPLACEMENT[modrm as usize] & (size.displaced as u8).wrapping_neg();
+    let len = size.len as usize + displacement as usize;
+    if len > bytes.len() {
+        return Err(DecodeError::Truncated { offset: 0 });
+    }
+    // Decoded whether or not there is an r/m operand, rather than branching on what sort of
+    // operand takes its place
+    let modrm = modrm & decoding.rm_mask | decoding.rm_bits;
+    let word = reader.word(1 + decoding.modrm as usize);
+    let parsed = parse_rm(word, modrm >> 6, modrm & 0b111, is_word);
+    let rm = select_unpredictable(decoding.has_rm, Some(parsed), decoding.implied);
+    let (destination, other) =
+        select_unpredictable(decoding.reg_first, (decoding.reg, rm), (rm, decoding.reg));
+    // Likewise every part the remaining operand could be made of is worked out, and its kind
+    // left to say which it is
+    let tail = decoding.tail;
+    let at = len - tail.len as usize;
+    let (word, segment) = (reader.word(at), reader.word(at + 2));
+    let byte = word as u8;
+    let value = select_unpredictable(tail.sign_extended, byte as i8 as u16, word & tail.mask)
+        | tail.fixed as u16;
+    // The base of aam and aad is only shown when it isn't the usual 10
+    let kind = select_unpredictable(tail.base & (byte == 10), SourceKind::Loc, tail.kind);
+    let size = prefix_len + len;
+    let source = SourceParts {
+        kind,
+        location: other,
+        value,
+        is_word: tail.word,
+        segment,
+        // Counted from the next instruction
+        target: (address + size).wrapping_add_signed(value as i16 as isize),
     };
+
     let instruction = Instruction {
-        _address: 0,
-        _size: 0,
-        operation: opcode,
+        address,
+        size,
+        is_word,
+        lock: prefixes.lock,
+        segment: prefixes.segment,
+        repeat: prefixes.repeat,
+        operation: decoding.op,
         destination,
         source,
     };
-    Ok((i, instruction))
+    Ok((instruction, size))
 }
 
-fn parse_immediate(i: BitInput, is_word: bool) -> IResult<BitInput, Immediate> {
-    let (i, low) = take(8u8)(i)?;
-    Ok(if !is_word {
-        (i, Immediate::Byte(low))
-    } else {
-        let (i, high): (BitInput, u16) = take(8u8)(i)?;
-        let high = high << 8;
-        let word = high + u16::from(low);
-        (i, Immediate::Word(word))
-    })
-}
-
-// TODO(matyas): remove is_word and mode parameters from RM parser
-fn parse_rm(mode: u8, w_bit: bool, i: BitInput) -> IResult<BitInput, Location> {
-    assert!(mode <= 3);
-    if let 0b11 = mode {
-        let (i, reg) = parse_reg(w_bit)(i)?;
-        Ok((i, Location::Reg(reg)))
-    } else {
-        let (i, addr) = parse_addr(i)?;
-        let (i, eaddr) = parse_eaddr(i, mode, addr)?;
-        Ok((i, Location::Addr(eaddr)))
+/// Number of displacement bytes by ModRM byte. Mod 01 is followed by a byte and mod 10 by a word,
+/// as is mod 00 with r/m 110 since there is no bare [bp], its encoding is taken by a direct address.
+static DISPLACEMENT: [u8; 256] = {
+    let mut lens = [0; 256];
+    let mut modrm = 0;
+    while modrm < 256 {
+        lens[modrm] = match (modrm >> 6, modrm & 0b111) {
+            (0b00, 0b110) | (0b10, _) => 2,
+            (0b01, _) => 1,
+            _ => 0,
+        };
+        modrm += 1;
     }
-}
-
-fn parse_eaddr(i: BitInput, mode: u8, addr: Address) -> IResult<BitInput, EAddress> {
-    let (i, eaddr) = if mode == 0 {
-        (i, EAddress::Bare(addr))
-    } else {
-        let is_word = mode == 0b10;
-        let (i, imm) = parse_immediate(i, is_word)?;
-        (i, EAddress::WithOffset(addr, imm))
-    };
+    lens
+};
 
-    Ok((i, eaddr))
+/// The bytes of an instruction following its prefixes. Reading never fails, bytes past the end
+/// read as zero and it is up to the caller to check the instruction fits.
+struct Reader {
+    /// An instruction is at most 6 bytes after its prefixes
+    bytes: [u8; 8],
 }
 
-fn parse_addr(i: BitInput) -> IResult<BitInput, Address> {
-    let (i, addr) = take_3bits(i)?;
-    use Address::*;
-    let addr = match addr {
-        0b000 => BxSi,
-        0b001 => BxDi,
-        0b010 => BpSi,
-        0b011 => BpDi,
-        0b100 => Si,
-        0b101 => Di,
-        0b110 => Bp,
-        _ => Bx,
-    };
-    Ok((i, addr))
-}
+impl Reader {
+    #[inline]
+    fn new(bytes: &[u8]) -> Self {
+        let bytes = match bytes.first_chunk() {
+            Some(chunk) => *chunk,
+            None => {
+                let mut padded = [0; 8];
+                padded[..bytes.len()].copy_from_slice(bytes);
+                padded
+            }
+        };
+        Reader { bytes }
+    }
 
-pub fn take_nibble(i: BitInput) -> IResult<BitInput, u8> {
-    take(4u8)(i)
+    /// Little endian 16 bit value `at` bytes in
+    fn word(&self, at: usize) -> u16 {
+        let low = self.bytes[at & 0b111];
+        let high = self.bytes[(at + 1) & 0b111];
+        u16::from_le_bytes([low, high])
+    }
 }
 
-pub fn take_3bits(i: BitInput) -> IResult<BitInput, u8> {
-    take(3u8)(i)
+/// Prefix bytes that modify the instruction following them
+#[derive(Default)]
+struct Prefixes {
+    lock: bool,
+    segment: Option<Register>,
+    repeat: Option<Repeat>,
 }
 
-pub fn take_2bits(i: BitInput) -> IResult<BitInput, u8> {
-    take(2u8)(i)
+/// Number of prefix bytes, and what they amount to
+fn parse_prefixes(bytes: &[u8]) -> (usize, Prefixes) {
+    let mut prefixes = Prefixes::default();
+    let mut len = 0;
+    while let Some(&prefix) = bytes.get(len) {
+        match prefix {
+            // 001 sr 110, the segment is used in place of the default for the memory operand
+            _ if prefix & 0b1110_0111 == 0b0010_0110 => {
+                prefixes.segment = Some(Register::segment((prefix >> 3) & 0b11))
+            }
+            0xf0 => prefixes.lock = true,
+            0xf2 => prefixes.repeat = Some(Repeat::Repne),
+            0xf3 => prefixes.repeat = Some(Repeat::Rep),
+            _ => break,
+        }
+        len += 1;
+    }
+    (len, prefixes)
 }
 
-pub fn parse_reg<I>(w_bit: bool) -> impl FnMut((I, usize)) -> IResult<(I, usize), Register>
-where
-    I: Slice<std::ops::RangeFrom<usize>> + InputIter<Item = u8> + InputLength,
-{
-    map(
-        take(3u8),
-        if w_bit {
-            Register::word
-        } else {
-            Register::byte
-        },
-    )
+/// The mode and r/m fields are as good as random to branch prediction, so rather than branch on
+/// them every form of the operand is worked out and the one they call for picked.
+/// `word` is the pair of bytes following the ModRM byte.
+#[inline]
+fn parse_rm(word: u16, mode: u8, rm: u8, w_bit: bool) -> Location {
+    let displacement = select_unpredictable(mode == 0b01, word as u8 as i8 as i16, word as i16);
+    let addr = parse_addr(rm);
+    let eaddr = select_unpredictable(
+        mode == 0b00,
+        select_unpredictable(rm == 0b110, EAddress::Direct(word), EAddress::Bare(addr)),
+        EAddress::WithOffset(addr, displacement),
+    );
+    let reg = Location::Reg(Register::sized(rm, w_bit));
+    select_unpredictable(mode == 0b11, reg, Location::Addr(eaddr))
 }
 
-pub fn parse_opcode(i: BitInput) -> IResult<BitInput, Op> {
-    let (i, partial) = take_nibble(i)?;
-    let (i, opcode) = match partial {
-        0b1000 => {
-            let (i, _) = take_2bits(i)?;
-            (i, Op::MovRegRM)
-        }
-        0b1011 => (i, Op::MovImmediateReg),
-        0b1100 => todo!("Immediate to register/memory"),
-        0b1010 => todo!("Memory to/from accumulator"),
-        _ => {
-            println!("partial: {partial:0b}");
-            println!("input: {:?}", i.0);
-            (i, Op::Unimplemented)
-        }
-    };
-    Ok((i, opcode))
+fn parse_addr(rm: u8) -> Address {
+    use Address::*;
+    // Looked up for the same reason as in `parse_rm`
+    static ADDRESSES: [Address; 8] = [BxSi, BxDi, BpSi, BpDi, Si, Di, Bp, Bx];
+    ADDRESSES[rm as usize & 0b111]
 }
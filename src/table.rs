@@ -0,0 +1,599 @@
+//! Every 8086 instruction encoding, written down the way the manual lays them out.
+//! The first encoding to match an instruction wins, so more specific patterns go first.
+
+use crate::{Location, Op, Register, SourceKind};
+use std::sync::OnceLock;
+use Field::*;
+
+/// A piece of an encoding, in the order the bits appear
+#[derive(Debug, Clone, Copy)]
+pub enum Field {
+    /// `(count, value)`, bits that have to match exactly
+    Bits(u8, u8),
+    /// Set when the reg field is the destination
+    D,
+    /// Operates on words rather than bytes
+    W,
+    /// Byte of data sign extended to a word
+    S,
+    /// Shift count is in cl rather than 1
+    V,
+    Mod,
+    Reg,
+    /// Followed by the displacement when `mod` calls for one
+    Rm,
+    /// Same as `Rm`, but has to address memory
+    RmMem,
+    /// Segment register, two bits in place of the reg field
+    Sr,
+    /// Byte or word of data depending on `W` and `S`
+    Data,
+    DataByte,
+    DataWord,
+    /// Byte of data that is only shown when it isn't the usual 10
+    Base,
+    /// Displacement of a relative jump
+    Rel8,
+    Rel16,
+    /// 16 bit direct address
+    Addr,
+    /// `offset16 | segment16`
+    FarPointer,
+    /// Three bits of the opcode passed to the coprocessor
+    Esc,
+    /// Implicit values, they take up no bits
+    ImpD(bool),
+    ImpW(bool),
+    ImpReg(u8),
+    ImpSr(u8),
+    ImpRm(Register),
+}
+
+impl Field {
+    /// Bits taken up in the opcode or ModRM byte, the byte sized fields following them don't count
+    fn width(self) -> u8 {
+        match self {
+            Bits(count, _) => count,
+            D | W | S | V => 1,
+            Mod | Sr => 2,
+            Reg | Rm | RmMem | Esc => 3,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Encoding {
+    pub op: Op,
+    pub fields: &'static [Field],
+}
+
+/// A field found at `shift` in the opcode and ModRM bytes, with the opcode in the high byte.
+/// An implicit field has no `mask` and is always `value`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BitField {
+    shift: u8,
+    mask: u8,
+    value: u8,
+}
+
+impl BitField {
+    fn at(used: u8, width: u8) -> Self {
+        BitField {
+            shift: 16 - used - width,
+            mask: ((1u16 << width) - 1) as u8,
+            value: 0,
+        }
+    }
+
+    fn implicit(value: u8) -> Self {
+        BitField {
+            value,
+            ..Default::default()
+        }
+    }
+
+    pub fn extract(self, bits: u16) -> u8 {
+        ((bits >> self.shift) as u8 & self.mask) | self.value
+    }
+
+    /// Inverse of `extract`, the bits to set for `value` if the field can hold it
+    pub fn insert(self, value: u8) -> Option<u16> {
+        if self.mask == 0 {
+            (value == self.value).then_some(0)
+        } else {
+            (value <= self.mask).then_some((value as u16) << self.shift)
+        }
+    }
+
+    /// Value of the field when the encoding leaves it implicit
+    pub fn value(self) -> Option<u8> {
+        (self.mask == 0).then_some(self.value)
+    }
+}
+
+/// Where an encoding keeps its fields, worked out once so decoding doesn't walk the field list
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    pub op: Op,
+    /// Followed by a ModRM byte, or whatever takes up its place
+    pub modrm: bool,
+    /// Fixed bits of the opcode and ModRM bytes, the opcode is in the high byte
+    pub mask: u16,
+    pub value: u16,
+    pub d: BitField,
+    pub w: BitField,
+    pub s: BitField,
+    pub v: BitField,
+    pub mode: BitField,
+    pub rm: BitField,
+    /// The reg field, or the segment register in its place when `sr` is set
+    pub reg: Option<BitField>,
+    pub sr: bool,
+    /// `Rm`, `RmMem`, `Addr` or `ImpRm`, whichever takes the place of the r/m operand
+    pub location: Option<Field>,
+    /// Any other operand, such as data or a jump
+    pub source: Option<Field>,
+    /// High and low bits of the escape opcode
+    pub esc: [BitField; 2],
+}
+
+impl Layout {
+    fn new(encoding: &Encoding) -> Self {
+        let mut layout = Layout {
+            op: encoding.op,
+            modrm: false,
+            mask: 0,
+            value: 0,
+            d: BitField::default(),
+            w: BitField::default(),
+            s: BitField::default(),
+            v: BitField::default(),
+            mode: BitField::default(),
+            rm: BitField::default(),
+            reg: None,
+            sr: false,
+            location: None,
+            source: None,
+            esc: [BitField::default(); 2],
+        };
+        let mut used = 0;
+        for &field in encoding.fields {
+            let at = BitField::at(used, field.width());
+            match field {
+                Bits(_, bits) => {
+                    layout.mask |= (at.mask as u16) << at.shift;
+                    layout.value |= (bits as u16) << at.shift;
+                }
+                D => layout.d = at,
+                W => layout.w = at,
+                S => layout.s = at,
+                V => {
+                    layout.v = at;
+                    layout.source = Some(V);
+                }
+                Mod => layout.mode = at,
+                Reg => layout.reg = Some(at),
+                Sr => (layout.reg, layout.sr) = (Some(at), true),
+                Rm | RmMem => {
+                    layout.rm = at;
+                    layout.location = Some(field);
+                }
+                Addr | ImpRm(_) => layout.location = Some(field),
+                Esc => {
+                    layout.esc = [layout.esc[1], at];
+                    layout.source = Some(Esc);
+                }
+                Data | DataByte | DataWord | Base | Rel8 | Rel16 | FarPointer => {
+                    layout.source = Some(field)
+                }
+                ImpD(d) => layout.d = BitField::implicit(d as u8),
+                ImpW(w) => layout.w = BitField::implicit(w as u8),
+                ImpReg(reg) => layout.reg = Some(BitField::implicit(reg)),
+                ImpSr(sr) => (layout.reg, layout.sr) = (Some(BitField::implicit(sr)), true),
+            }
+            used += field.width();
+        }
+        layout.modrm = used > 8;
+        layout
+    }
+
+    /// `bits` are the opcode and the byte following it
+    fn matches(&self, bits: u16) -> bool {
+        bits & self.mask == self.value
+    }
+}
+
+/// What an opcode and the reg field after it settle about an instruction, worked out for every
+/// one of them up front so decoding is left with the mod and r/m fields and the bytes that follow
+#[derive(Debug, Clone, Copy)]
+pub struct Decoding {
+    pub op: Op,
+    pub modrm: bool,
+    pub is_word: bool,
+    /// Operand of the reg field
+    pub reg: Option<Location>,
+    /// Set when the reg operand comes first, that is when the d bit is or there is no other one
+    pub reg_first: bool,
+    /// The r/m operand is decoded from the mod and r/m fields
+    pub has_rm: bool,
+    /// Applied to the ModRM byte ahead of decoding the r/m operand, a direct address
+    /// being the same as mod 00 and r/m 110
+    pub rm_mask: u8,
+    pub rm_bits: u8,
+    /// Modes that aren't allowed, a bit for each, mode 11 when the r/m operand has to address
+    /// memory and every mode when no encoding matches
+    pub bad_modes: u8,
+    /// Operand implied by the opcode in place of the r/m one
+    pub implied: Option<Location>,
+    pub tail: Tail,
+}
+
+/// How many bytes an instruction takes up, kept apart from the rest of its decoding since the
+/// next instruction has to wait on it
+#[derive(Debug, Clone, Copy)]
+pub struct Size {
+    /// Bytes taken up after the prefixes, other than the displacement
+    pub len: u8,
+    /// Followed by the displacement the mod and r/m fields call for
+    pub displaced: bool,
+}
+
+impl Size {
+    fn new(layout: &Layout, tail: Tail) -> Self {
+        let addr = matches!(layout.location, Some(Addr));
+        Size {
+            len: 1 + layout.modrm as u8 + 2 * addr as u8 + tail.len,
+            displaced: matches!(layout.location, Some(Rm | RmMem)),
+        }
+    }
+}
+
+/// Operand that `Layout::source` stands for, with whatever the opcode says about it applied.
+/// Kept as plain values so decoding can work out every form of it and pick one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tail {
+    /// Bytes it takes up
+    pub len: u8,
+    /// Sort of source it makes
+    pub kind: SourceKind,
+    /// Bits of the word read that make up the value
+    pub mask: u16,
+    /// Byte read sign extended to a word
+    pub sign_extended: bool,
+    /// Value set by the opcode rather than read, a shift count of 1 or an escape opcode
+    pub fixed: u8,
+    /// The immediate is a word, whether read as one or sign extended
+    pub word: bool,
+    /// Immediate that is left out when it's the usual 10
+    pub base: bool,
+}
+
+impl Decoding {
+    /// `bits` are the opcode and a byte with the reg field of the instruction
+    fn new(layout: &Layout, bits: u16) -> Self {
+        let is_word = layout.w.extract(bits) == 1;
+        let reg = layout.reg.map(|reg| {
+            let reg = reg.extract(bits);
+            match (layout.sr, is_word) {
+                (true, _) => Register::segment(reg),
+                (false, true) => Register::word(reg),
+                (false, false) => Register::byte(reg),
+            }
+        });
+        let imm = |len| Tail {
+            len,
+            kind: SourceKind::Imm,
+            mask: match len {
+                0 => 0,
+                1 => 0xff,
+                _ => 0xffff,
+            },
+            ..Tail::default()
+        };
+        let tail = match layout.source {
+            Some(Data) if is_word && layout.s.extract(bits) == 1 => Tail {
+                sign_extended: true,
+                word: true,
+                ..imm(1)
+            },
+            Some(Data) if is_word => Tail {
+                word: true,
+                ..imm(2)
+            },
+            Some(Data | DataByte) => imm(1),
+            Some(DataWord) => Tail {
+                word: true,
+                ..imm(2)
+            },
+            Some(Base) => Tail {
+                base: true,
+                ..imm(1)
+            },
+            Some(Rel8) => Tail {
+                len: 1,
+                kind: SourceKind::Jump,
+                sign_extended: true,
+                ..Tail::default()
+            },
+            Some(Rel16) => Tail {
+                len: 2,
+                kind: SourceKind::Jump,
+                mask: 0xffff,
+                ..Tail::default()
+            },
+            Some(FarPointer) => Tail {
+                len: 4,
+                kind: SourceKind::Far,
+                mask: 0xffff,
+                ..Tail::default()
+            },
+            Some(V) if layout.v.extract(bits) == 1 => Tail::default(),
+            Some(V) => Tail { fixed: 1, ..imm(0) },
+            Some(Esc) => {
+                let [high, low] = layout.esc.map(|bits_at| bits_at.extract(bits));
+                Tail {
+                    fixed: (high << 3) | low,
+                    ..imm(0)
+                }
+            }
+            _ => Tail::default(),
+        };
+        // A shift count of cl is decoded as the reg operand, which shifts go without
+        let cl = matches!(layout.source, Some(V)) && layout.v.extract(bits) == 1;
+        let reg = if cl { Some(Register::Cl) } else { reg };
+        let location = layout.location;
+        let has_rm = matches!(location, Some(Rm | RmMem | Addr));
+        let (rm_mask, rm_bits) = match location {
+            Some(Addr) => (0, 0b00_000_110),
+            _ => (0xff, 0),
+        };
+        let implied = match location {
+            Some(ImpRm(register)) => Some(Location::Reg(register)),
+            _ => None,
+        };
+        Decoding {
+            op: layout.op,
+            modrm: layout.modrm,
+            is_word,
+            reg: reg.map(Location::Reg),
+            reg_first: reg.is_some() && !cl && (layout.d.extract(bits) == 1 || location.is_none()),
+            has_rm,
+            rm_mask,
+            rm_bits,
+            bad_modes: if matches!(location, Some(RmMem)) {
+                0b1000
+            } else {
+                0
+            },
+            implied,
+            tail,
+        }
+    }
+}
+
+/// Decodings are looked up by the opcode and the reg field of the byte after it
+const DECODINGS: usize = 256 * 8;
+
+struct Layouts {
+    layouts: Vec<Layout>,
+    /// Decoding of the first matching layout for every opcode and reg field
+    decodings: Box<[Decoding; DECODINGS]>,
+    /// Size of each of the decodings
+    sizes: Box<[Size; DECODINGS]>,
+    /// Opcodes with an encoding for at least one reg field
+    known: [bool; 256],
+}
+
+#[inline]
+fn compiled() -> &'static Layouts {
+    static LAYOUTS: OnceLock<Layouts> = OnceLock::new();
+    LAYOUTS.get_or_init(|| {
+        let layouts: Vec<Layout> = ENCODINGS.iter().map(Layout::new).collect();
+        // Left in place where no encoding matches, it fails the mode check whatever the mode
+        let unmatched = Decoding {
+            bad_modes: 0b1111,
+            ..Decoding::new(&layouts[0], 0)
+        };
+        let mut decodings = Box::new([unmatched; DECODINGS]);
+        let mut sizes = Box::new([Size::new(&layouts[0], unmatched.tail); DECODINGS]);
+        let mut known = [false; 256];
+        for (index, (decoding, size)) in decodings.iter_mut().zip(sizes.iter_mut()).enumerate() {
+            let (opcode, reg) = ((index >> 3) as u8, (index & 0b111) as u8);
+            let bits = u16::from_be_bytes([opcode, reg << 3]);
+            if let Some(layout) = layouts.iter().find(|layout| layout.matches(bits)) {
+                *decoding = Decoding::new(layout, bits);
+                *size = Size::new(layout, decoding.tail);
+                known[opcode as usize] = true;
+            }
+        }
+        Layouts {
+            layouts,
+            decodings,
+            sizes,
+            known,
+        }
+    })
+}
+
+/// Layouts of every encoding, in table order
+pub fn layouts() -> &'static [Layout] {
+    &compiled().layouts
+}
+
+/// Decoding of the first encoding matching the opcode and the reg field of the byte after it,
+/// which is enough to tell every encoding apart, along with its size.
+/// Without a match every mode is disallowed.
+#[inline]
+pub fn decoding(opcode: u8, modrm: u8) -> (Size, &'static Decoding) {
+    let index = (opcode as usize) << 3 | (modrm as usize >> 3) & 0b111;
+    let compiled = compiled();
+    (compiled.sizes[index], &compiled.decodings[index])
+}
+
+/// Whether any encoding starts with the opcode, telling an unknown opcode from a bad ModRM byte
+pub fn is_known(opcode: u8) -> bool {
+    compiled().known[opcode as usize]
+}
+
+const fn enc(op: Op, fields: &'static [Field]) -> Encoding {
+    Encoding { op, fields }
+}
+
+#[rustfmt::skip]
+pub static ENCODINGS: &[Encoding] = &[
+    enc(Op::Mov, &[Bits(6, 0b100010), D, W, Mod, Reg, Rm]),
+    enc(Op::Mov, &[Bits(7, 0b1100011), W, Mod, Bits(3, 0b000), Rm, Data]),
+    enc(Op::Mov, &[Bits(4, 0b1011), W, Reg, Data, ImpD(true)]),
+    enc(Op::Mov, &[Bits(7, 0b1010000), W, Addr, ImpReg(0), ImpD(true)]),
+    enc(Op::Mov, &[Bits(7, 0b1010001), W, Addr, ImpReg(0)]),
+    enc(Op::Mov, &[Bits(8, 0b10001110), Mod, Bits(1, 0), Sr, Rm, ImpW(true), ImpD(true)]),
+    enc(Op::Mov, &[Bits(8, 0b10001100), Mod, Bits(1, 0), Sr, Rm, ImpW(true)]),
+
+    enc(Op::Push, &[Bits(8, 0b11111111), Mod, Bits(3, 0b110), Rm, ImpW(true)]),
+    enc(Op::Push, &[Bits(5, 0b01010), Reg, ImpW(true)]),
+    enc(Op::Push, &[Bits(3, 0b000), Sr, Bits(3, 0b110), ImpW(true)]),
+    enc(Op::Pop, &[Bits(8, 0b10001111), Mod, Bits(3, 0b000), Rm, ImpW(true)]),
+    enc(Op::Pop, &[Bits(5, 0b01011), Reg, ImpW(true)]),
+    // 0x0f would be pop cs, which the 8086 does run, but nothing emits it
+    enc(Op::Pop, &[Bits(8, 0b00000111), ImpSr(0b00), ImpW(true)]),
+    enc(Op::Pop, &[Bits(8, 0b00010111), ImpSr(0b10), ImpW(true)]),
+    enc(Op::Pop, &[Bits(8, 0b00011111), ImpSr(0b11), ImpW(true)]),
+
+    enc(Op::Nop, &[Bits(8, 0b10010000)]),
+    enc(Op::Xchg, &[Bits(7, 0b1000011), W, Mod, Reg, Rm, ImpD(true)]),
+    enc(Op::Xchg, &[Bits(5, 0b10010), Reg, ImpW(true), ImpRm(Register::Ax)]),
+
+    enc(Op::In, &[Bits(7, 0b1110010), W, DataByte, ImpReg(0), ImpD(true)]),
+    enc(Op::In, &[Bits(7, 0b1110110), W, ImpReg(0), ImpRm(Register::Dx), ImpD(true)]),
+    // Shown the other way around, the port is the destination
+    enc(Op::Out, &[Bits(7, 0b1110011), W, DataByte, ImpReg(0), ImpD(true)]),
+    enc(Op::Out, &[Bits(7, 0b1110111), W, ImpReg(0), ImpRm(Register::Dx), ImpD(true)]),
+
+    enc(Op::Xlat, &[Bits(8, 0b11010111)]),
+    enc(Op::Lea, &[Bits(8, 0b10001101), Mod, Reg, RmMem, ImpW(true), ImpD(true)]),
+    enc(Op::Lds, &[Bits(8, 0b11000101), Mod, Reg, RmMem, ImpW(true), ImpD(true)]),
+    enc(Op::Les, &[Bits(8, 0b11000100), Mod, Reg, RmMem, ImpW(true), ImpD(true)]),
+    enc(Op::Lahf, &[Bits(8, 0b10011111)]),
+    enc(Op::Sahf, &[Bits(8, 0b10011110)]),
+    enc(Op::Pushf, &[Bits(8, 0b10011100)]),
+    enc(Op::Popf, &[Bits(8, 0b10011101)]),
+
+    enc(Op::Add, &[Bits(6, 0b000000), D, W, Mod, Reg, Rm]),
+    enc(Op::Add, &[Bits(6, 0b100000), S, W, Mod, Bits(3, 0b000), Rm, Data]),
+    enc(Op::Add, &[Bits(7, 0b0000010), W, Data, ImpReg(0), ImpD(true)]),
+    enc(Op::Or, &[Bits(6, 0b000010), D, W, Mod, Reg, Rm]),
+    enc(Op::Or, &[Bits(6, 0b100000), S, W, Mod, Bits(3, 0b001), Rm, Data]),
+    enc(Op::Or, &[Bits(7, 0b0000110), W, Data, ImpReg(0), ImpD(true)]),
+    enc(Op::Adc, &[Bits(6, 0b000100), D, W, Mod, Reg, Rm]),
+    enc(Op::Adc, &[Bits(6, 0b100000), S, W, Mod, Bits(3, 0b010), Rm, Data]),
+    enc(Op::Adc, &[Bits(7, 0b0001010), W, Data, ImpReg(0), ImpD(true)]),
+    enc(Op::Sbb, &[Bits(6, 0b000110), D, W, Mod, Reg, Rm]),
+    enc(Op::Sbb, &[Bits(6, 0b100000), S, W, Mod, Bits(3, 0b011), Rm, Data]),
+    enc(Op::Sbb, &[Bits(7, 0b0001110), W, Data, ImpReg(0), ImpD(true)]),
+    enc(Op::And, &[Bits(6, 0b001000), D, W, Mod, Reg, Rm]),
+    enc(Op::And, &[Bits(6, 0b100000), S, W, Mod, Bits(3, 0b100), Rm, Data]),
+    enc(Op::And, &[Bits(7, 0b0010010), W, Data, ImpReg(0), ImpD(true)]),
+    enc(Op::Sub, &[Bits(6, 0b001010), D, W, Mod, Reg, Rm]),
+    enc(Op::Sub, &[Bits(6, 0b100000), S, W, Mod, Bits(3, 0b101), Rm, Data]),
+    enc(Op::Sub, &[Bits(7, 0b0010110), W, Data, ImpReg(0), ImpD(true)]),
+    enc(Op::Xor, &[Bits(6, 0b001100), D, W, Mod, Reg, Rm]),
+    enc(Op::Xor, &[Bits(6, 0b100000), S, W, Mod, Bits(3, 0b110), Rm, Data]),
+    enc(Op::Xor, &[Bits(7, 0b0011010), W, Data, ImpReg(0), ImpD(true)]),
+    enc(Op::Cmp, &[Bits(6, 0b001110), D, W, Mod, Reg, Rm]),
+    enc(Op::Cmp, &[Bits(6, 0b100000), S, W, Mod, Bits(3, 0b111), Rm, Data]),
+    enc(Op::Cmp, &[Bits(7, 0b0011110), W, Data, ImpReg(0), ImpD(true)]),
+
+    enc(Op::Inc, &[Bits(7, 0b1111111), W, Mod, Bits(3, 0b000), Rm]),
+    enc(Op::Inc, &[Bits(5, 0b01000), Reg, ImpW(true)]),
+    enc(Op::Dec, &[Bits(7, 0b1111111), W, Mod, Bits(3, 0b001), Rm]),
+    enc(Op::Dec, &[Bits(5, 0b01001), Reg, ImpW(true)]),
+    enc(Op::Neg, &[Bits(7, 0b1111011), W, Mod, Bits(3, 0b011), Rm]),
+    enc(Op::Not, &[Bits(7, 0b1111011), W, Mod, Bits(3, 0b010), Rm]),
+    enc(Op::Mul, &[Bits(7, 0b1111011), W, Mod, Bits(3, 0b100), Rm]),
+    enc(Op::Imul, &[Bits(7, 0b1111011), W, Mod, Bits(3, 0b101), Rm]),
+    enc(Op::Div, &[Bits(7, 0b1111011), W, Mod, Bits(3, 0b110), Rm]),
+    enc(Op::Idiv, &[Bits(7, 0b1111011), W, Mod, Bits(3, 0b111), Rm]),
+    enc(Op::Test, &[Bits(7, 0b1000010), W, Mod, Reg, Rm]),
+    enc(Op::Test, &[Bits(7, 0b1111011), W, Mod, Bits(3, 0b000), Rm, Data]),
+    enc(Op::Test, &[Bits(7, 0b1010100), W, Data, ImpReg(0), ImpD(true)]),
+
+    enc(Op::Aaa, &[Bits(8, 0b00110111)]),
+    enc(Op::Daa, &[Bits(8, 0b00100111)]),
+    enc(Op::Aas, &[Bits(8, 0b00111111)]),
+    enc(Op::Das, &[Bits(8, 0b00101111)]),
+    enc(Op::Aam, &[Bits(8, 0b11010100), Base]),
+    enc(Op::Aad, &[Bits(8, 0b11010101), Base]),
+    enc(Op::Cbw, &[Bits(8, 0b10011000)]),
+    enc(Op::Cwd, &[Bits(8, 0b10011001)]),
+
+    enc(Op::Rol, &[Bits(6, 0b110100), V, W, Mod, Bits(3, 0b000), Rm]),
+    enc(Op::Ror, &[Bits(6, 0b110100), V, W, Mod, Bits(3, 0b001), Rm]),
+    enc(Op::Rcl, &[Bits(6, 0b110100), V, W, Mod, Bits(3, 0b010), Rm]),
+    enc(Op::Rcr, &[Bits(6, 0b110100), V, W, Mod, Bits(3, 0b011), Rm]),
+    enc(Op::Shl, &[Bits(6, 0b110100), V, W, Mod, Bits(3, 0b100), Rm]),
+    enc(Op::Shr, &[Bits(6, 0b110100), V, W, Mod, Bits(3, 0b101), Rm]),
+    enc(Op::Sar, &[Bits(6, 0b110100), V, W, Mod, Bits(3, 0b111), Rm]),
+
+    enc(Op::Movsb, &[Bits(8, 0b10100100)]),
+    enc(Op::Movsw, &[Bits(8, 0b10100101), ImpW(true)]),
+    enc(Op::Cmpsb, &[Bits(8, 0b10100110)]),
+    enc(Op::Cmpsw, &[Bits(8, 0b10100111), ImpW(true)]),
+    enc(Op::Stosb, &[Bits(8, 0b10101010)]),
+    enc(Op::Stosw, &[Bits(8, 0b10101011), ImpW(true)]),
+    enc(Op::Lodsb, &[Bits(8, 0b10101100)]),
+    enc(Op::Lodsw, &[Bits(8, 0b10101101), ImpW(true)]),
+    enc(Op::Scasb, &[Bits(8, 0b10101110)]),
+    enc(Op::Scasw, &[Bits(8, 0b10101111), ImpW(true)]),
+
+    enc(Op::Call, &[Bits(8, 0b11101000), Rel16, ImpW(true)]),
+    enc(Op::Call, &[Bits(8, 0b11111111), Mod, Bits(3, 0b010), Rm, ImpW(true)]),
+    enc(Op::Call, &[Bits(8, 0b10011010), FarPointer, ImpW(true)]),
+    enc(Op::CallFar, &[Bits(8, 0b11111111), Mod, Bits(3, 0b011), RmMem, ImpW(true)]),
+    enc(Op::Jmp, &[Bits(8, 0b11101001), Rel16, ImpW(true)]),
+    enc(Op::Jmp, &[Bits(8, 0b11101011), Rel8]),
+    enc(Op::Jmp, &[Bits(8, 0b11111111), Mod, Bits(3, 0b100), Rm, ImpW(true)]),
+    enc(Op::Jmp, &[Bits(8, 0b11101010), FarPointer, ImpW(true)]),
+    enc(Op::JmpFar, &[Bits(8, 0b11111111), Mod, Bits(3, 0b101), RmMem, ImpW(true)]),
+    enc(Op::Ret, &[Bits(8, 0b11000011)]),
+    enc(Op::Ret, &[Bits(8, 0b11000010), DataWord, ImpW(true)]),
+    enc(Op::Retf, &[Bits(8, 0b11001011)]),
+    enc(Op::Retf, &[Bits(8, 0b11001010), DataWord, ImpW(true)]),
+
+    enc(Op::Jo, &[Bits(8, 0b01110000), Rel8]),
+    enc(Op::Jno, &[Bits(8, 0b01110001), Rel8]),
+    enc(Op::Jb, &[Bits(8, 0b01110010), Rel8]),
+    enc(Op::Jnb, &[Bits(8, 0b01110011), Rel8]),
+    enc(Op::Je, &[Bits(8, 0b01110100), Rel8]),
+    enc(Op::Jne, &[Bits(8, 0b01110101), Rel8]),
+    enc(Op::Jbe, &[Bits(8, 0b01110110), Rel8]),
+    enc(Op::Ja, &[Bits(8, 0b01110111), Rel8]),
+    enc(Op::Js, &[Bits(8, 0b01111000), Rel8]),
+    enc(Op::Jns, &[Bits(8, 0b01111001), Rel8]),
+    enc(Op::Jp, &[Bits(8, 0b01111010), Rel8]),
+    enc(Op::Jnp, &[Bits(8, 0b01111011), Rel8]),
+    enc(Op::Jl, &[Bits(8, 0b01111100), Rel8]),
+    enc(Op::Jnl, &[Bits(8, 0b01111101), Rel8]),
+    enc(Op::Jle, &[Bits(8, 0b01111110), Rel8]),
+    enc(Op::Jg, &[Bits(8, 0b01111111), Rel8]),
+    enc(Op::Loopnz, &[Bits(8, 0b11100000), Rel8]),
+    enc(Op::Loopz, &[Bits(8, 0b11100001), Rel8]),
+    enc(Op::Loop, &[Bits(8, 0b11100010), Rel8]),
+    enc(Op::Jcxz, &[Bits(8, 0b11100011), Rel8]),
+
+    enc(Op::Int, &[Bits(8, 0b11001101), DataByte]),
+    enc(Op::Int3, &[Bits(8, 0b11001100)]),
+    enc(Op::Into, &[Bits(8, 0b11001110)]),
+    enc(Op::Iret, &[Bits(8, 0b11001111)]),
+
+    enc(Op::Clc, &[Bits(8, 0b11111000)]),
+    enc(Op::Cmc, &[Bits(8, 0b11110101)]),
+    enc(Op::Stc, &[Bits(8, 0b11111001)]),
+    enc(Op::Cld, &[Bits(8, 0b11111100)]),
+    enc(Op::Std, &[Bits(8, 0b11111101)]),
+    enc(Op::Cli, &[Bits(8, 0b11111010)]),
+    enc(Op::Sti, &[Bits(8, 0b11111011)]),
+    enc(Op::Hlt, &[Bits(8, 0b11110100)]),
+    enc(Op::Wait, &[Bits(8, 0b10011011)]),
+    enc(Op::Esc, &[Bits(5, 0b11011), Esc, Mod, Esc, Rm, ImpW(true)]),
+];
@@ -1,9 +1,5 @@
-use crate::disassemble;
-use rand::{distributions::Alphanumeric, Rng};
-use std::{
-    fs::{self, remove_file},
-    process::Command,
-};
+use crate::{assemble, decode, decode_instruction, disassemble, AssembleError, DecodeError};
+use std::fs;
 
 #[test]
 fn file_validation() {
@@ -20,77 +16,376 @@ fn listing38() {
     validate_listing("listing38")
 }
 
-// #[test]
-// fn listing39() {
-//     validate_listing("listing39")
-// }
+#[test]
+fn listing39() {
+    validate_listing("listing39")
+}
 
-// #[test]
-// fn listing40() {
-//     validate_listing("listing40")
-// }
+#[test]
+fn listing40() {
+    validate_listing("listing40")
+}
 
+mod cpu;
+mod fuzz;
 mod listing39;
-// mod listing40;
+mod listing40;
+mod listing41;
+mod listing42;
+mod trace;
 
-/// Takes a listing name (eg. "listing37")
-/// - assembles its asm file
-/// - dissassembles the binary and saves it to a file
-/// - assembles the dissassembly and finaly compares the two binaries
-fn validate_listing(listing: &str) {
-    let src_path = format!("./listings/{}.asm", listing);
+#[test]
+fn undecodable_bytes() {
+    let asm = disassemble(&[0xf1, 0x89, 0xd9, 0x89]);
+    assert_eq!(
+        asm,
+        "bits 16\n\n\
+        db 0xf1 ; unknown opcode 0xf1 at offset 0\n\
+        mov cx, bx\n\
+        db 0x89 ; truncated instruction at offset 3\n"
+    );
+}
 
-    let (bin1, bin2) = produce_bins(&src_path);
-    assert_eq!(bin1, bin2);
+#[test]
+fn instruction_address_and_size() {
+    let program = [0x89, 0xd9, 0xc7, 0x85, 0x85, 0x03, 0x5b, 0x01, 0x75, 0xf6];
+
+    let mov = decode_instruction(&program, 0).unwrap();
+    assert_eq!((mov.address(), mov.size()), (0, 2));
+
+    let mov = decode_instruction(&program, 2).unwrap();
+    assert_eq!((mov.address(), mov.size()), (2, 6));
+    assert_eq!(mov.raw_bytes(&program), &program[2..8]);
+
+    let jne = decode_instruction(&program, 8).unwrap();
+    assert_eq!((jne.address(), jne.size()), (8, 2));
+    assert_eq!(jne.to_string(), "jne $-8");
+
+    // The slice form takes the address of the slice in the program
+    let (jne, size) = decode(&program[8..], 8).unwrap();
+    assert_eq!((jne.to_string(), size), ("jne $-8".to_string(), 2));
+    assert_eq!(
+        decode(&program[8..9], 8).unwrap_err(),
+        DecodeError::Truncated { offset: 8 }
+    );
+}
+
+#[test]
+fn jump_labels() {
+    let program = [
+        0xb9, 0x03, 0x00, 0x83, 0xe9, 0x01, 0x75, 0xfb, 0xe3, 0x7f, 0xeb,
+    ];
+    assert_eq!(
+        disassemble(&program),
+        "bits 16\n\n\
+        mov cx, 3\n\
+        label_0:\n\
+        sub cx, 1\n\
+        jne label_0\n\
+        jcxz $+129\n\
+        db 0xeb ; truncated instruction at offset 10\n"
+    );
+}
+
+#[test]
+fn near_jump_in_short_reach() {
+    let program = [0xe9, 0x01, 0x00, 0x90, 0x90];
+    let disassembly = disassemble(&program);
+    assert_eq!(
+        disassembly,
+        "bits 16\n\njmp near label_0\nnop\nlabel_0:\nnop\n"
+    );
+    assert_eq!(assemble(&disassembly).as_deref(), Ok(&program[..]));
+    assert_eq!(
+        assemble("jmp short $+2\njmp near $+3\njmp $+2").as_deref(),
+        Ok(&[0xeb, 0x00, 0xe9, 0x00, 0x00, 0xeb, 0x00][..])
+    );
 }
-fn validate_asm(asm: &str) {
-    println!("{}", asm);
 
-    let binary_path: String = rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(7)
-        .map(char::from)
+#[test]
+fn every_opcode_byte() {
+    // 0x0f is pop cs, the rest are aliases and leftovers of the 8086 opcode map
+    let undefined: Vec<u8> = [0x0f, 0xc0, 0xc1, 0xc8, 0xc9, 0xd6, 0xf1]
+        .into_iter()
+        .chain(0x60..=0x6f)
         .collect();
-    let src_path = &format!("listings/tmp/{}.asm", binary_path);
 
-    fs::write(src_path, asm).unwrap();
-    let (dasm_bin, asm_bin) = produce_bins(src_path);
+    for opcode in 0..=u8::MAX {
+        // Enough zeroes to fill any operands
+        let program = [opcode, 0, 0, 0, 0, 0, 0];
+        let decoded = decode_instruction(&program, 0);
+        if undefined.contains(&opcode) {
+            assert_eq!(
+                decoded.unwrap_err(),
+                DecodeError::UnknownOpcode { offset: 0, opcode }
+            );
+        } else {
+            assert!(decoded.is_ok(), "0x{opcode:02x}: {decoded:?}");
+        }
+    }
+}
 
-    if let Err(err) = remove_file(src_path) {
-        eprintln!("{}", err);
-    };
+#[test]
+fn encode_round_trip() {
+    let programs: &[&[u8]] = &[
+        &[0x89, 0xd9],
+        &[0x88, 0xe5],
+        &[0xa1, 0x10, 0x00],
+        &[0xa2, 0xfa, 0x09],
+        &[0xc6, 0x07, 0x07],
+        &[0xc7, 0x85, 0x85, 0x03, 0x5b, 0x01],
+        &[0x8b, 0x46, 0x00],
+        &[0x8a, 0x40, 0xdb],
+        &[0x83, 0xc0, 0x05],
+        &[0x81, 0xc1, 0xe8, 0x03],
+        &[0x05, 0xe8, 0x03],
+        &[0x04, 0xe2],
+        &[0x80, 0x3f, 0x22],
+        &[0x75, 0xfb],
+        &[0xe8, 0x00, 0x01],
+        &[0xea, 0x88, 0x77, 0x66, 0x55],
+        &[0xff, 0x1f],
+        &[0x26, 0x8b, 0x07],
+        &[0xf3, 0xa4],
+        &[0xf0, 0xfe, 0x07],
+        &[0xd3, 0xe8],
+        &[0xd1, 0xe0],
+        &[0xe4, 0x40],
+        &[0xec],
+        &[0xcd, 0x21],
+        &[0xcc],
+        &[0xd4, 0x0a],
+        &[0xd5, 0x07],
+        &[0x91],
+        &[0x90],
+        &[0x86, 0xc8],
+        &[0x8d, 0x47, 0x04],
+        &[0x8e, 0xd8],
+        &[0x8c, 0xc0],
+        &[0x06],
+        &[0x1f],
+        &[0xc2, 0x04, 0x00],
+        &[0xe2, 0xfe],
+        &[0xd8, 0xc1],
+    ];
+    for &program in programs {
+        let instruction = decode_instruction(program, 0).unwrap();
+        assert_eq!(instruction.encode(), program, "{instruction}");
+    }
+}
+
+#[test]
+fn encode_picks_shortest() {
+    let encodings: &[(&[u8], &[u8])] = &[
+        (&[0x8b, 0x06, 0x10, 0x00], &[0xa1, 0x10, 0x00]),
+        (&[0x81, 0xc0, 0x05, 0x00], &[0x83, 0xc0, 0x05]),
+        (&[0x8b, 0xcb], &[0x89, 0xd9]),
+        (&[0x8b, 0x86, 0x04, 0x00], &[0x8b, 0x46, 0x04]),
+    ];
+    for &(long, short) in encodings {
+        let instruction = decode_instruction(long, 0).unwrap();
+        assert_eq!(instruction.encode(), short, "{instruction}");
+    }
+}
 
-    assert_eq!(dasm_bin, asm_bin);
+#[test]
+fn encode_decoded_instructions() {
+    let modrms = [0x00, 0x06, 0x17, 0x46, 0x5a, 0x86, 0xb3, 0xc1, 0xd8, 0xff];
+    for opcode in 0..=u8::MAX {
+        for modrm in modrms {
+            let program = [opcode, modrm, 0x34, 0x92, 0x78, 0x56, 0x00];
+            let Ok(instruction) = decode_instruction(&program, 0) else {
+                continue;
+            };
+            let bytes = instruction.encode();
+            let encoded = decode_instruction(&bytes, 0).map(|encoded| encoded.to_string());
+            assert_eq!(
+                encoded,
+                Ok(instruction.to_string()),
+                "{:02x?} -> {bytes:02x?}",
+                &program[..instruction.size()]
+            );
+        }
+    }
 }
-fn produce_bins(src_path: &str) -> (Vec<u8>, Vec<u8>) {
-    let produce_bin = |src_path, bin_path| {
-        Command::new("nasm")
-            .args([src_path])
-            .output()
-            .and_then(|_| fs::read(bin_path))
-    };
-    let (src_path, bin_path) = src_bin_paths(src_path);
-    let src_path2 = format!("{}-d.asm", bin_path);
-    let (src_path2, bin_path2) = src_bin_paths(&src_path2);
 
-    let bin1 = produce_bin(src_path, bin_path).expect("Assembly compiled and read");
+#[test]
+fn golden_diff() {
+    assert_eq!(
+        diff(
+            "mov cx, bx\nmov ch, ah\nhlt\n",
+            "mov cx, bx\nmov ch, al\nhlt\nnop\n"
+        ),
+        "   1  mov cx, bx\n   2 -mov ch, ah\n   2 +mov ch, al\n   3  hlt\n   4 +nop\n"
+    );
+}
 
-    let bin2 =
-        fs::write(src_path2, disassemble(&bin1)).and_then(|_| produce_bin(src_path2, bin_path2));
+#[test]
+fn assemble_instructions() {
+    let program = assemble(
+        "
+        bits 16
 
-    if let Err(err) = remove_file(src_path2) {
-        eprintln!("{}", err);
-    };
-    if let Err(err) = remove_file(bin_path) {
-        eprintln!("{}", err);
-    };
-    if let Err(err) = remove_file(bin_path2) {
-        eprintln!("{}", err);
+        mov cx, bx
+        mov ch, -12
+        mov dx, [bp]
+        mov [bp + di], byte 7
+        add ss:[2555], word 7
+        mov ax, es:[bx + si]
+        xchg [bx], al
+        xchg si, ax
+        out 44, al
+        in ax, dx
+        esc 5, [bx]
+        call 4660:22136
+        jmp far [5]
+        rep movsb
+        int 33
+        ret 4
+        ret -2
+        retf -2
+        shl byte [bx], cl
+        ",
+    );
+    assert_eq!(
+        program.unwrap(),
+        [
+            0x89, 0xd9, 0xb5, 0xf4, 0x8b, 0x56, 0x00, 0xc6, 0x03, 0x07, 0x36, 0x83, 0x06, 0xfb,
+            0x09, 0x07, 0x26, 0x8b, 0x00, 0x86, 0x07, 0x96, 0xe6, 0x2c, 0xed, 0xd8, 0x2f, 0x9a,
+            0x78, 0x56, 0x34, 0x12, 0xff, 0x2e, 0x05, 0x00, 0xf3, 0xa4, 0xcd, 0x21, 0xc2, 0x04,
+            0x00, 0xc2, 0xfe, 0xff, 0xca, 0xfe, 0xff, 0xd2, 0x27,
+        ]
+    );
+}
+
+#[test]
+fn assemble_labels_and_data() {
+    let program = assemble(
+        "
+        org 0x100
+        start:
+            jmp end ; skips the data
+            db 'hi', 0
+            dw start, -1
+            times 3 nop
+        end: jne start
+            mov ax, [value]
+        value: dw 1234h
+        ",
+    );
+    assert_eq!(
+        program.unwrap(),
+        [
+            0xeb, 0x0a, 0x68, 0x69, 0x00, 0x00, 0x01, 0xff, 0xff, 0x90, 0x90, 0x90, 0x75, 0xf2,
+            0xa1, 0x11, 0x01, 0x34, 0x12,
+        ]
+    );
+
+    let program = assemble("jmp far_away\ntimes 200 db 0\nfar_away:").unwrap();
+    assert_eq!(program[..3], [0xe9, 0xc8, 0x00]);
+
+    let program = assemble("org 0x7c00\nmov ax, 1\ntimes 510-($-$$) db 0\ndw 0xaa55").unwrap();
+    assert_eq!((program.len(), program[510]), (512, 0x55));
+}
+
+#[test]
+fn assembly_errors() {
+    let errors = [
+        ("mov [bx], 5", AssembleError::UnsizedOperand { line: 1 }),
+        ("mov al, 300", AssembleError::OutOfRange { line: 1 }),
+        ("mov ax, bl", AssembleError::InvalidOperands { line: 1 }),
+        ("bits 32", AssembleError::UnsupportedBits { line: 1 }),
+        ("\nmov ax,, bx", AssembleError::Syntax { line: 2 }),
+        (
+            "jmp nowhere",
+            AssembleError::UndefinedLabel {
+                line: 1,
+                label: "nowhere".to_string(),
+            },
+        ),
+        (
+            "here: nop\nhere: nop",
+            AssembleError::DuplicateLabel {
+                line: 2,
+                label: "here".to_string(),
+            },
+        ),
+        (
+            "jne far_away\ntimes 200 nop\nfar_away:",
+            AssembleError::OutOfRange { line: 1 },
+        ),
+    ];
+    for (asm, error) in errors {
+        assert_eq!(assemble(asm), Err(error), "{asm}");
+    }
+}
+
+/// Takes a listing name (eg. "listing37") and checks its checked-in files against each other:
+/// - the disassembly of the binary matches the golden text
+/// - the asm file and the golden text both assemble to the binary
+fn validate_listing(listing: &str) {
+    let read = |extension| {
+        let path = format!("./listings/{listing}.{extension}");
+        fs::read_to_string(&path).unwrap_or_else(|err| panic!("{path}: {err}"))
     };
+    let (asm, golden) = (read("asm"), read("golden"));
+    let program = fs::read(format!("./listings/{listing}.bin")).expect("Binary read");
 
-    (bin1, bin2.expect("Disassembly compiled and read"))
+    let disassembly = disassemble(&program);
+    if disassembly != golden {
+        panic!(
+            "{listing}: disassembly differs from the golden text (-golden +disassembly)\n{}",
+            diff(&golden, &disassembly)
+        );
+    }
+    assert_eq!(assemble(&asm).as_deref(), Ok(&program[..]), "{listing}.asm");
+    assert_eq!(
+        assemble(&golden).as_deref(),
+        Ok(&program[..]),
+        "{listing}.golden"
+    );
 }
 
-fn src_bin_paths(src_path: &str) -> (&str, &str) {
-    (src_path, src_path.get(..src_path.len() - 4).unwrap())
+/// Line by line difference, the lines only in `expected` start with `-`
+/// and the ones only in `actual` with `+`
+fn diff(expected: &str, actual: &str) -> String {
+    let (expected, actual): (Vec<_>, Vec<_>) =
+        (expected.lines().collect(), actual.lines().collect());
+    // common[i][j] is the length of the longest common subsequence of expected[i..] and actual[j..]
+    let mut common = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            diff.push_str(&format!("{:4}  {}\n", i + 1, expected[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if j == actual.len() || (i < expected.len() && common[i + 1][j] >= common[i][j + 1])
+        {
+            diff.push_str(&format!("{:4} -{}\n", i + 1, expected[i]));
+            i += 1;
+        } else {
+            diff.push_str(&format!("{:4} +{}\n", j + 1, actual[j]));
+            j += 1;
+        }
+    }
+    diff
+}
+fn validate_asm(asm: &str) {
+    println!("{}", asm);
+
+    let program = assemble(asm).expect("Assembly assembled");
+    let disassembly = disassemble(&program);
+    let reassembled = assemble(&disassembly).expect("Disassembly assembled");
+
+    assert_eq!(program, reassembled, "{disassembly}");
 }
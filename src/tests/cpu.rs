@@ -0,0 +1,578 @@
+use crate::{
+    assemble, decode_instruction, Address, Cpu, DecodeError, EAddress, ExecuteError, Flag, Memory,
+    Register, StopReason,
+};
+use Register::*;
+
+/// Assembles the program and runs it until it leaves the image
+fn execute(asm: &str) -> Cpu {
+    let (cpu, stop) = run(asm, 10_000);
+    assert_eq!(stop, StopReason::LeftImage);
+    cpu
+}
+
+/// Assembles the program, loads it at 0:0 and runs it
+fn run(asm: &str, budget: usize) -> (Cpu, StopReason) {
+    let program = assemble(asm).expect("Assembly assembled");
+    let mut cpu = Cpu::default();
+    cpu.load(&program);
+    let stop = cpu.run(budget);
+    (cpu, stop)
+}
+
+fn assert_registers(cpu: &Cpu, expected: &[(Register, u16)]) {
+    for &(register, value) in expected {
+        assert_eq!(cpu.register(register), value, "{register}");
+    }
+}
+
+#[test]
+fn immediate_movs() {
+    let cpu = execute(
+        "
+        mov ax, 1
+        mov bx, 2
+        mov cx, 3
+        mov dx, 4
+        mov sp, 5
+        mov bp, 6
+        mov si, 7
+        mov di, 8
+        ",
+    );
+    assert_registers(
+        &cpu,
+        &[
+            (Ax, 1),
+            (Bx, 2),
+            (Cx, 3),
+            (Dx, 4),
+            (Sp, 5),
+            (Bp, 6),
+            (Si, 7),
+            (Di, 8),
+        ],
+    );
+}
+
+#[test]
+fn register_movs() {
+    let cpu = execute(
+        "
+        mov ax, 1
+        mov bx, 2
+        mov cx, 3
+        mov dx, 4
+
+        mov sp, ax
+        mov bp, bx
+        mov si, cx
+        mov di, dx
+
+        mov dx, sp
+        mov cx, bp
+        mov bx, si
+        mov ax, di
+        ",
+    );
+    assert_registers(
+        &cpu,
+        &[
+            (Ax, 4),
+            (Bx, 3),
+            (Cx, 2),
+            (Dx, 1),
+            (Sp, 1),
+            (Bp, 2),
+            (Si, 3),
+            (Di, 4),
+        ],
+    );
+}
+
+#[test]
+fn byte_and_segment_movs() {
+    let cpu = execute(
+        "
+        mov ax, 0x2222
+        mov bx, 0x4444
+        mov cx, 0x6666
+        mov dx, 0x8888
+
+        mov ss, ax
+        mov ds, bx
+        mov es, cx
+
+        mov al, 0x11
+        mov bh, 0x33
+        mov cl, 0x55
+        mov dh, 0x77
+
+        mov ah, bl
+        mov cl, dh
+
+        mov ss, ax
+        mov ds, bx
+        mov es, cx
+
+        mov sp, ss
+        mov bp, ds
+        mov si, es
+        mov di, dx
+        ",
+    );
+    assert_registers(
+        &cpu,
+        &[
+            (Ax, 0x4411),
+            (Bx, 0x3344),
+            (Cx, 0x6677),
+            (Dx, 0x7788),
+            (Sp, 0x4411),
+            (Bp, 0x3344),
+            (Si, 0x6677),
+            (Di, 0x7788),
+            (Es, 0x6677),
+            (Ss, 0x4411),
+            (Ds, 0x3344),
+            (Ah, 0x44),
+            (Al, 0x11),
+        ],
+    );
+}
+
+#[test]
+fn high_byte_register_writes() {
+    let mut cpu = Cpu::default();
+    cpu.set_register(Ax, 0x1234);
+    cpu.set_register(Ah, 0xab);
+    assert_eq!(cpu.register(Ax), 0xab34);
+    cpu.set_register(Al, 0x1cd);
+    assert_eq!(cpu.register(Ax), 0xabcd);
+    assert_eq!((cpu.register(Ah), cpu.register(Al)), (0xab, 0xcd));
+}
+
+/// The arithmetic flags that are set, as letters
+fn set_flags(cpu: &Cpu) -> String {
+    [
+        (Flag::Carry, 'C'),
+        (Flag::Parity, 'P'),
+        (Flag::Auxiliary, 'A'),
+        (Flag::Zero, 'Z'),
+        (Flag::Sign, 'S'),
+        (Flag::Overflow, 'O'),
+    ]
+    .into_iter()
+    .filter(|&(flag, _)| cpu.flag(flag))
+    .map(|(_, letter)| letter)
+    .collect()
+}
+
+#[test]
+fn arithmetic_flags() {
+    // The program, the register holding the result, and the result and flags it ends with
+    let cases = [
+        ("mov ax, 0x7fff\nadd ax, 1", Ax, 0x8000, "PASO"),
+        ("mov al, 0x80\nsub al, 1", Al, 0x7f, "AO"),
+        ("mov al, 0xff\nadd al, 1", Al, 0x00, "CPAZ"),
+        ("mov al, 0x0f\nadd al, 0x01", Al, 0x10, "A"),
+        ("mov al, 0x00\nsub al, 1", Al, 0xff, "CPAS"),
+        ("mov ax, 0x10\nsub ax, 1", Ax, 0x0f, "PA"),
+        ("mov ax, 0x8000\nadd ax, 0x8000", Ax, 0x0000, "CPZO"),
+        (
+            "mov bl, 0xff\nadd bl, 1\nmov al, 0xfe\nadc al, 1",
+            Al,
+            0x00,
+            "CPAZ",
+        ),
+        (
+            "mov bl, 0xff\nadd bl, 1\nmov ax, 5\nsbb ax, 5",
+            Ax,
+            0xffff,
+            "CPAS",
+        ),
+        (
+            "mov bl, 1\nadd bl, 1\nmov ax, 5\nsbb ax, 5",
+            Ax,
+            0x0000,
+            "PZ",
+        ),
+        ("mov ax, 3\ncmp ax, 5", Ax, 0x0003, "CAS"),
+        ("mov cx, 0x7fff\ncmp cx, 0xffff", Cx, 0x7fff, "CPSO"),
+        (
+            "mov bl, 0xff\nadd bl, 1\nmov al, 0xff\ninc al",
+            Al,
+            0x00,
+            "CPAZ",
+        ),
+        ("mov al, 0xff\ninc al", Al, 0x00, "PAZ"),
+        ("mov ax, 0x8000\ndec ax", Ax, 0x7fff, "PAO"),
+        (
+            "mov bl, 0xff\nadd bl, 1\nmov bx, 1\ndec bx",
+            Bx,
+            0x0000,
+            "CPZ",
+        ),
+        ("mov al, 0x80\nneg al", Al, 0x80, "CSO"),
+        ("mov ax, 0\nneg ax", Ax, 0x0000, "PZ"),
+        ("mov ax, 1\nneg ax", Ax, 0xffff, "CPAS"),
+        (
+            "mov al, 0xff\nadd al, 1\nmov al, 0xf0\nand al, 0x0f",
+            Al,
+            0x00,
+            "PZ",
+        ),
+        ("mov ax, 0x8000\nxor ax, 1", Ax, 0x8001, "S"),
+        ("mov al, 0x81\ntest al, 0x80", Al, 0x81, "S"),
+        ("mov al, 3\nor al, 0", Al, 0x03, "P"),
+        ("mov bx, 0xff00\nadd bx, 0x0100", Bx, 0x0000, "CPZ"),
+        ("mov cx, 0xffff\nadd cx, -1", Cx, 0xfffe, "CAS"),
+    ];
+    for (asm, register, result, flags) in cases {
+        let cpu = execute(asm);
+        assert_eq!(
+            (cpu.register(register), set_flags(&cpu).as_str()),
+            (result, flags),
+            "{asm}"
+        );
+    }
+}
+
+#[test]
+fn add_sub_cmp() {
+    let cpu = execute(
+        "
+        mov bx, -4093
+        mov cx, 3841
+        sub bx, cx
+
+        mov sp, 998
+        mov bp, 999
+        cmp bp, sp
+
+        add bp, 1027
+        sub bp, 2026
+        ",
+    );
+    assert_registers(&cpu, &[(Bx, 0xe102), (Cx, 0x0f01), (Sp, 0x03e6), (Bp, 0)]);
+    assert_eq!(set_flags(&cpu), "PZ");
+}
+
+#[test]
+fn memory_movs() {
+    let cpu = execute(
+        "
+        mov word [1000], 1
+        mov word [1002], 2
+        mov word [1004], 3
+        mov word [1006], 4
+
+        mov bx, 1000
+        mov word [bx + 4], 10
+
+        mov bx, word [1000]
+        mov cx, word [1002]
+        mov dx, word [1004]
+        mov bp, word [1006]
+        ",
+    );
+    assert_registers(&cpu, &[(Bx, 1), (Cx, 2), (Dx, 10), (Bp, 4)]);
+    assert_eq!(cpu.memory().read_byte(0, 1004), 10);
+}
+
+#[test]
+fn default_and_override_segments() {
+    let cpu = execute(
+        "
+        mov ax, 0x1000
+        mov ss, ax
+        mov ax, 0x2000
+        mov ds, ax
+
+        mov bp, 4
+        mov word [bp], 0x1234
+        mov bx, 4
+        mov word [bx], 0x5678
+
+        mov cx, ds:[bp]
+        mov dx, ss:[bx]
+        add byte [bp + si], 1
+        ",
+    );
+    assert_registers(&cpu, &[(Cx, 0x5678), (Dx, 0x1234)]);
+    let memory = cpu.memory();
+    assert_eq!(memory.read_word(0x1000, 4), 0x1235);
+    assert_eq!(memory.read_word(0x2000, 4), 0x5678);
+    // The same bytes through other segments
+    assert_eq!(memory.read_byte(0x0fff, 0x14), 0x35);
+    assert_eq!(memory.read_byte(0x1fff, 0x14), 0x78);
+}
+
+#[test]
+fn effective_addresses() {
+    let mut cpu = Cpu::default();
+    for (register, value) in [
+        (Bx, 0x10),
+        (Bp, 0x20),
+        (Si, 0x1),
+        (Di, 0x2),
+        (Ss, 0x300),
+        (Ds, 0x400),
+        (Es, 0x500),
+    ] {
+        cpu.set_register(register, value);
+    }
+    let cases = [
+        (EAddress::Direct(0x1234), None, (0x400, 0x1234)),
+        (EAddress::Bare(Address::BxSi), None, (0x400, 0x11)),
+        (EAddress::Bare(Address::BxDi), None, (0x400, 0x12)),
+        (EAddress::Bare(Address::BpSi), None, (0x300, 0x21)),
+        (EAddress::Bare(Address::BpDi), None, (0x300, 0x22)),
+        (EAddress::Bare(Address::Si), None, (0x400, 0x1)),
+        (EAddress::Bare(Address::Di), None, (0x400, 0x2)),
+        (
+            EAddress::WithOffset(Address::Bp, -0x21),
+            None,
+            (0x300, 0xffff),
+        ),
+        (
+            EAddress::WithOffset(Address::Bx, 0x100),
+            None,
+            (0x400, 0x110),
+        ),
+        (
+            EAddress::WithOffset(Address::Bp, 0),
+            Some(Es),
+            (0x500, 0x20),
+        ),
+        (EAddress::Direct(0x8), Some(Ss), (0x300, 0x8)),
+    ];
+    for (eaddr, segment, expected) in cases {
+        assert_eq!(
+            cpu.effective_address(&eaddr, segment),
+            expected,
+            "{eaddr:?} {segment:?}"
+        );
+    }
+}
+
+#[test]
+fn physical_addresses_and_wrapping() {
+    assert_eq!(Memory::physical(0x1234, 0x5678), 0x179b8);
+    assert_eq!(Memory::physical(0xffff, 0x000f), 0xfffff);
+    // Past the end of the 1 MiB, the address wraps around to the start
+    assert_eq!(Memory::physical(0xffff, 0x0010), 0x00000);
+    assert_eq!(Memory::physical(0xffff, 0xffff), 0x0ffef);
+
+    let mut memory = Memory::default();
+    memory.write_word(0x100, 0x10, 0xabcd);
+    assert_eq!(
+        (memory.read_byte(0x101, 0), memory.read_byte(0x101, 1)),
+        (0xcd, 0xab)
+    );
+    // A word at the very end of a segment wraps to its start, rather than into the next one
+    memory.write_word(0x100, 0xffff, 0x1234);
+    assert_eq!(memory.read_byte(0x100, 0xffff), 0x34);
+    assert_eq!(memory.read_byte(0x100, 0x0000), 0x12);
+    assert_eq!(memory.read_byte(0x1100, 0x0000), 0x00);
+    assert_eq!(memory.read_word(0x100, 0xffff), 0x1234);
+}
+
+#[test]
+fn unsupported_instructions() {
+    let program = assemble("mov ax, 1\nxlat").unwrap();
+    let instruction = decode_instruction(&program, 3).unwrap();
+    assert_eq!(
+        Cpu::default().step(&instruction),
+        Err(ExecuteError::Unsupported { address: 3 })
+    );
+}
+
+#[test]
+fn conditional_jumps() {
+    // Listing 49 of the course
+    let (cpu, stop) = run(
+        "
+        mov cx, 3
+        mov bx, 1000
+        loop_start:
+        add bx, 10
+        sub cx, 1
+        jnz loop_start
+        ",
+        1_000,
+    );
+    assert_eq!(stop, StopReason::LeftImage);
+    assert_registers(&cpu, &[(Bx, 1030), (Cx, 0)]);
+    assert_eq!(cpu.ip(), 0x0e);
+    assert_eq!(set_flags(&cpu), "PZ");
+}
+
+#[test]
+fn challenge_jumps() {
+    // Listing 50 of the course
+    let (cpu, stop) = run(
+        "
+        mov ax, 10
+        mov bx, 10
+        mov cx, 10
+        label_0:
+        cmp bx, cx
+        je label_1
+        add ax, 1
+        jp label_2
+        label_1:
+        sub bx, 5
+        jb label_3
+        label_2:
+        sub cx, 2
+        label_3:
+        loopnz label_0
+        ",
+        1_000,
+    );
+    assert_eq!(stop, StopReason::LeftImage);
+    assert_registers(&cpu, &[(Ax, 13), (Bx, 0xfffb), (Cx, 0)]);
+    assert_eq!(cpu.ip(), 0x1c);
+    assert_eq!(set_flags(&cpu), "CAS");
+}
+
+#[test]
+fn loops() {
+    let (cpu, _) = run(
+        "
+        mov cx, 5
+        counted:
+        inc ax
+        loop counted
+        mov cx, 5
+        until_zero:
+        inc bx
+        cmp bx, 7
+        loopnz until_zero
+        mov cx, 5
+        while_zero:
+        inc dx
+        cmp cx, cx
+        loopz while_zero
+        jcxz done
+        mov si, 1
+        done:
+        ",
+        1_000,
+    );
+    assert_registers(&cpu, &[(Ax, 5), (Bx, 5), (Dx, 5), (Cx, 0), (Si, 0)]);
+}
+
+#[test]
+fn calls_and_returns() {
+    let (cpu, stop) = run(
+        "
+        mov sp, 0x1000
+        mov ax, 1
+        call double
+        call double
+        mov bx, double
+        call bx
+        push ax
+        call release
+        hlt
+        double:
+        add ax, ax
+        ret
+        release:
+        ret 2
+        ",
+        1_000,
+    );
+    assert_eq!(stop, StopReason::Halt);
+    // ret 2 releases the pushed argument
+    assert_registers(&cpu, &[(Ax, 8), (Sp, 0x1000)]);
+}
+
+#[test]
+fn far_calls_and_jumps() {
+    let (cpu, stop) = run(
+        "
+        mov sp, 0x1000
+        call 0:far_procedure
+        mov word [0x2000], indirect
+        mov word [0x2002], 0
+        call far [0x2000]
+        mov word [0x2000], other_segment
+        jmp far [0x2000]
+        far_procedure:
+        inc ax
+        retf
+        indirect:
+        inc bx
+        retf
+        other_segment:
+        ; The same physical address as done, from the next segment
+        jmp 1:done - 16
+        done:
+        ",
+        1_000,
+    );
+    assert_eq!(stop, StopReason::LeftImage);
+    assert_registers(&cpu, &[(Ax, 1), (Bx, 1), (Cs, 1), (Sp, 0x1000)]);
+    assert_eq!(Memory::physical(1, cpu.ip()), 0x2b);
+}
+
+#[test]
+fn pushes_and_pops() {
+    let cpu = execute(
+        "
+        mov sp, 0x1000
+        mov ax, 0x1234
+        mov word [0x2000], 0x5678
+        push ax
+        push word [0x2000]
+        push ds
+        mov ds, ax
+        pop ds
+        pop bx
+        pop word [0x2002]
+        push sp
+        pop cx
+        ",
+    );
+    assert_registers(&cpu, &[(Bx, 0x5678), (Ds, 0), (Cx, 0x0ffe), (Sp, 0x1000)]);
+    assert_eq!(cpu.memory().read_word(0, 0x2002), 0x1234);
+}
+
+#[test]
+fn stop_reasons() {
+    assert_eq!(run("mov ax, 1\nhlt\nmov ax, 2", 10).1, StopReason::Halt);
+    assert_eq!(run("here: jmp here", 1_000).1, StopReason::Budget);
+    assert_eq!(run("mov ax, 1\njmp 0x1000", 10).1, StopReason::LeftImage);
+    assert_eq!(
+        run("mov ax, 1\ndb 0xf1", 10).1,
+        StopReason::Decode(DecodeError::UnknownOpcode {
+            offset: 3,
+            opcode: 0xf1
+        })
+    );
+    assert_eq!(
+        run("mov ax, 1\ndb 0xb8, 0x01", 10).1,
+        StopReason::Decode(DecodeError::Truncated { offset: 3 })
+    );
+    assert_eq!(
+        run("mov ax, 1\nxlat", 10).1,
+        StopReason::Execute(ExecuteError::Unsupported { address: 3 })
+    );
+
+    // Nothing past the image is decoded, so mov ax, 1 isn't finished with zeroes from memory
+    let mut cpu = Cpu::default();
+    cpu.load(&[0xb8, 0x01]);
+    assert_eq!(
+        cpu.run(10),
+        StopReason::Decode(DecodeError::Truncated { offset: 0 })
+    );
+    assert_registers(&cpu, &[(Ax, 0)]);
+
+    let (cpu, stop) = run("mov ax, 1\nmov bx, 2\nmov cx, 3", 2);
+    assert_eq!(stop, StopReason::Budget);
+    assert_registers(&cpu, &[(Bx, 2), (Cx, 0)]);
+    assert_eq!(cpu.ip(), 6);
+}
@@ -0,0 +1,147 @@
+use crate::table::{layouts, Field};
+use crate::{
+    assemble, decode_instruction, disassemble, EAddress, Immediate, Instruction, Location, Source,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+/// Instruction bytes built from a random encoding of the table, so that every encoding
+/// added to it gets covered. The fields of the encoding get random values,
+/// and random bytes follow for whatever displacement and data they call for.
+/// Also gives the number of prefixes.
+fn random_instruction(rng: &mut StdRng) -> (Vec<u8>, usize) {
+    // At most one prefix of each kind, in the order nasm emits them, repeat, lock and segment
+    let mut bytes = Vec::new();
+    for prefixes in [&[0xf2, 0xf3][..], &[0xf0], &[0x26, 0x2e, 0x36, 0x3e]] {
+        if rng.gen_ratio(1, 8) {
+            bytes.push(*prefixes.choose(rng).unwrap());
+        }
+    }
+
+    let prefix_len = bytes.len();
+
+    let layout = layouts().choose(rng).unwrap();
+    let mut bits = layout.value | (rng.gen::<u16>() & !layout.mask);
+    if let Some(Field::RmMem) = layout.location {
+        let register_mode = layout.mode.insert(0b11).unwrap();
+        let memory_mode = layout.mode.insert(rng.gen_range(0b00..0b11)).unwrap();
+        bits = (bits & !register_mode) | memory_mode;
+    }
+    let [opcode, modrm] = bits.to_be_bytes();
+    bytes.push(opcode);
+    if layout.modrm {
+        bytes.push(modrm);
+    }
+    bytes.extend((0..4).map(|_| rng.gen::<u8>()));
+    (bytes, prefix_len)
+}
+
+/// Forms nasm never emits, since the same instruction has a shorter or preferred encoding which
+/// the assembler picks as well. `bytes` start at the opcode.
+fn non_canonical(instruction: &Instruction, bytes: &[u8]) -> Option<&'static str> {
+    let [opcode, modrm, ..] = *bytes else {
+        return None;
+    };
+    let (mode, reg, rm) = (modrm >> 6, (modrm >> 3) & 0b111, modrm & 0b111);
+    let source = instruction.source();
+    let imm_fits_byte = matches!(
+        source,
+        Some(Source::Imm(Immediate::Word(value))) if value as i16 == value as i8 as i16
+    );
+    let loaded = match source {
+        Some(Source::Loc(location)) => Some(location),
+        _ => None,
+    };
+    let displacement = [instruction.destination, loaded]
+        .into_iter()
+        .find_map(|location| match location {
+            Some(Location::Addr(EAddress::WithOffset(_, displacement))) => Some(displacement),
+            _ => None,
+        });
+    Some(match opcode {
+        0x82 => "0x82, the same as 0x80",
+        // A register source goes in the reg field
+        0x00..=0x3b | 0x88..=0x8b if opcode & 0b110 == 0b010 && mode == 0b11 => {
+            "d bit set between two registers"
+        }
+        0x88..=0x8b if mode == 0b00 && rm == 0b110 && reg == 0 => {
+            "accumulator mov of a direct address"
+        }
+        0x80 | 0x81 if mode == 0b11 && rm == 0 => "accumulator as the r/m operand",
+        0xf6 | 0xf7 if mode == 0b11 && reg == 0 && rm == 0 => "accumulator test as the r/m operand",
+        0x87 if mode == 0b11 && (reg == 0 || rm == 0) => "xchg with ax",
+        0xc6 | 0xc7 | 0x8f if mode == 0b11 => "register as the r/m operand of mov or pop",
+        0xff if mode == 0b11 && matches!(reg, 0 | 1 | 6) => {
+            "register as the r/m operand of inc, dec or push"
+        }
+        0x05 | 0x0d | 0x15 | 0x1d | 0x25 | 0x2d | 0x35 | 0x3d | 0x81 if imm_fits_byte => {
+            "word immediate that a sign extended byte holds"
+        }
+        _ if mode == 0b10 && displacement.is_some_and(|value| value == value as i8 as i16) => {
+            "word displacement that a sign extended byte holds"
+        }
+        _ if mode == 0b01 && rm != 0b110 && displacement == Some(0) => "zero displacement",
+        _ => return None,
+    })
+}
+
+#[test]
+fn reassembly_is_a_fixed_point() {
+    let mut rng = StdRng::seed_from_u64(8086);
+    for _ in 0..20_000 {
+        let (bytes, prefix_len) = random_instruction(&mut rng);
+        let decoded = decode_instruction(&bytes, 0)
+            .unwrap_or_else(|err| panic!("{bytes:02x?} doesn't decode: {err}"));
+        let text = decoded.to_string();
+        let raw = decoded.raw_bytes(&bytes);
+
+        let reassembled = assemble(&text).unwrap_or_else(|err| panic!("{text}: {err}"));
+        match non_canonical(&decoded, &raw[prefix_len..]) {
+            None => assert_eq!(reassembled, raw, "{text}"),
+            Some(form) => assert!(
+                reassembled.len() <= raw.len() && reassembled != raw,
+                "{text}, {form}: {raw:02x?} -> {reassembled:02x?}"
+            ),
+        }
+        let redecoded = decode_instruction(&reassembled, 0).unwrap().to_string();
+        assert_eq!(
+            normalized(&redecoded),
+            normalized(&text),
+            "{:02x?} -> {reassembled:02x?}",
+            decoded.raw_bytes(&bytes)
+        );
+        assert_eq!(assemble(&redecoded), Ok(reassembled), "{redecoded}");
+    }
+}
+
+/// An exchange comes back with its operands either way around,
+/// and `xchg ax, ax` as the one byte `nop` like nasm assembles it
+fn normalized(text: &str) -> String {
+    let Some((prefixes, operands)) = text.split_once("xchg ") else {
+        return text.to_string();
+    };
+    let mut operands: Vec<&str> = operands.split(", ").collect();
+    operands.sort();
+    match operands[..] {
+        ["ax", "ax"] => format!("{prefixes}nop"),
+        _ => format!("{prefixes}xchg {}", operands.join(", ")),
+    }
+}
+
+#[test]
+fn random_bytes_decode_without_panicking() {
+    let mut rng = StdRng::seed_from_u64(86);
+    for _ in 0..2_000 {
+        let len = rng.gen_range(0..64);
+        let program: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+        for offset in 0..program.len() {
+            match decode_instruction(&program, offset) {
+                Ok(instruction) => {
+                    assert!(instruction.size() > 0);
+                    assert!(offset + instruction.size() <= program.len());
+                }
+                Err(err) => assert_eq!(err.offset(), offset),
+            }
+        }
+        disassemble(&program);
+    }
+}
@@ -0,0 +1,107 @@
+use super::validate_asm;
+
+#[test]
+fn register_memory() {
+    validate_asm(
+        "
+bits 16
+
+add bx, [bx + si]
+add bx, [bp]
+add [bp + si + 4], bh
+sub [bx + 2], cx
+cmp di, [bp + di + 6]
+        ",
+    );
+}
+
+#[test]
+fn immediate_to_register_memory() {
+    validate_asm(
+        "
+bits 16
+
+add si, 2
+add byte [bx], 34
+sub word [bp + si + 1000], 29
+cmp cx, 1000
+        ",
+    );
+}
+
+#[test]
+fn immediate_to_accumulator() {
+    validate_asm(
+        "
+bits 16
+
+add ax, 1000
+sub al, 9
+cmp ax, 1000
+        ",
+    );
+}
+
+#[test]
+fn logic() {
+    validate_asm(
+        "
+bits 16
+
+or al, ah
+and [bx + si], dx
+xor cx, cx
+adc ax, [bx]
+sbb dl, 5
+        ",
+    );
+}
+
+#[test]
+fn conditional_jumps() {
+    validate_asm(
+        "
+bits 16
+
+test_label0:
+jnz test_label1
+jnz test_label0
+test_label1:
+jnz test_label0
+jnz test_label1
+
+label:
+je label
+jl label
+jle label
+jb label
+jbe label
+jp label
+jo label
+js label
+jne label
+jnl label
+jg label
+jnb label
+ja label
+jnp label
+jno label
+jns label
+        ",
+    );
+}
+
+#[test]
+fn loops() {
+    validate_asm(
+        "
+bits 16
+
+label:
+loop label
+loopz label
+loopnz label
+jcxz label
+        ",
+    );
+}
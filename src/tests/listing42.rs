@@ -0,0 +1,251 @@
+use super::validate_asm;
+
+#[test]
+fn segment_registers() {
+    validate_asm(
+        "
+bits 16
+
+mov ds, ax
+mov es, [bx + si + 4]
+mov [bp + 2], ss
+mov cx, cs
+push es
+push cs
+pop ss
+pop ds
+        ",
+    );
+}
+
+#[test]
+fn segment_overrides() {
+    validate_asm(
+        "
+bits 16
+
+mov ax, es:[bx + si]
+mov cs:[bp - 6], cl
+add ss:[2555], word 7
+        ",
+    );
+}
+
+#[test]
+fn string_instructions() {
+    validate_asm(
+        "
+bits 16
+
+movsb
+movsw
+cmpsb
+cmpsw
+scasb
+scasw
+lodsb
+lodsw
+stosb
+stosw
+        ",
+    );
+}
+
+#[test]
+fn repeat_prefixes() {
+    validate_asm(
+        "
+bits 16
+
+rep movsb
+repe cmpsw
+repne scasb
+rep lodsw
+rep stosb
+        ",
+    );
+}
+
+#[test]
+fn shifts_and_rotates() {
+    validate_asm(
+        "
+bits 16
+
+shl ax, 1
+shr bl, cl
+sar word [bp + si + 5], 1
+rol byte [bx], cl
+ror dx, 1
+rcl byte [di - 3], 1
+rcr si, cl
+        ",
+    );
+}
+
+#[test]
+fn multiply_divide_group() {
+    validate_asm(
+        "
+bits 16
+
+test bl, 5
+test word [bx], 1000
+not byte [bx]
+neg ax
+mul cl
+imul word [bp + 9]
+div byte [16]
+idiv cx
+        ",
+    );
+}
+
+#[test]
+fn stack() {
+    validate_asm(
+        "
+bits 16
+
+push ax
+push word [bp + si]
+push ds
+pop bx
+pop word [16]
+pop es
+pushf
+popf
+        ",
+    );
+}
+
+#[test]
+fn calls_and_returns() {
+    validate_asm(
+        "
+bits 16
+
+label:
+call label
+call 4660:22136
+call ax
+call [bx + si + 4]
+call far [bp - 8]
+ret
+ret 4
+retf
+retf 8
+        ",
+    );
+}
+
+#[test]
+fn unconditional_jumps() {
+    validate_asm(
+        "
+bits 16
+
+label:
+jmp label
+jmp 61440:0
+jmp di
+jmp [bx]
+jmp far [5]
+        ",
+    );
+}
+
+#[test]
+fn increment_decrement() {
+    validate_asm(
+        "
+bits 16
+
+inc ax
+dec di
+inc byte [bx]
+dec word [bp + 4]
+inc dl
+        ",
+    );
+}
+
+#[test]
+fn load_and_exchange() {
+    validate_asm(
+        "
+bits 16
+
+lea ax, [bx + si + 5]
+lds si, [bx]
+les bx, [16]
+xchg al, [bx]
+xchg dx, cx
+xchg ax, si
+xlat
+lahf
+sahf
+cbw
+cwd
+        ",
+    );
+}
+
+#[test]
+fn ports() {
+    validate_asm(
+        "
+bits 16
+
+in al, 200
+in ax, 44
+out 44, al
+out 44, ax
+in al, dx
+in ax, dx
+out dx, al
+out dx, ax
+        ",
+    );
+}
+
+#[test]
+fn interrupts_and_processor_control() {
+    validate_asm(
+        "
+bits 16
+
+int 33
+int3
+into
+iret
+clc
+stc
+cmc
+cld
+std
+cli
+sti
+hlt
+wait
+nop
+lock xchg [bx], al
+        ",
+    );
+}
+
+#[test]
+fn decimal_adjust() {
+    validate_asm(
+        "
+bits 16
+
+daa
+das
+aaa
+aas
+aam
+aad
+        ",
+    );
+}
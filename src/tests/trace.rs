@@ -0,0 +1,79 @@
+use crate::{assemble, trace, Cpu, StopReason};
+
+/// Assembles the program, loads it at 0:0 and traces it
+fn trace_asm(asm: &str) -> String {
+    let mut cpu = Cpu::default();
+    cpu.load(&assemble(asm).expect("Assembly assembled"));
+    let (text, stop) = trace(&mut cpu, 1_000);
+    assert_eq!(stop, StopReason::LeftImage);
+    text
+}
+
+#[test]
+fn add_sub_cmp() {
+    let text = trace_asm(
+        "
+        mov bx, -4093
+        mov cx, 3841
+        sub bx, cx
+        mov sp, 998
+        mov bp, 999
+        cmp bp, sp
+        add bp, 1027
+        sub bp, 2026
+        ",
+    );
+    assert_eq!(
+        text,
+        "\
+mov bx, 61443 ; bx:0x0->0xf003 ip:0x0->0x3 \n\
+mov cx, 3841 ; cx:0x0->0xf01 ip:0x3->0x6 \n\
+sub bx, cx ; bx:0xf003->0xe102 ip:0x6->0x8 flags:->S \n\
+mov sp, 998 ; sp:0x0->0x3e6 ip:0x8->0xb \n\
+mov bp, 999 ; bp:0x0->0x3e7 ip:0xb->0xe \n\
+cmp bp, sp ; ip:0xe->0x10 flags:S-> \n\
+add bp, 1027 ; bp:0x3e7->0x7ea ip:0x10->0x14 \n\
+sub bp, 2026 ; bp:0x7ea->0x0 ip:0x14->0x18 flags:->PZ \n
+Final registers:
+      bx: 0xe102 (57602)
+      cx: 0x0f01 (3841)
+      sp: 0x03e6 (998)
+      ip: 0x0018 (24)
+   flags: PZ
+"
+    );
+}
+
+#[test]
+fn conditional_jumps() {
+    let text = trace_asm(
+        "
+        mov cx, 3
+        mov bx, 1000
+        loop_start:
+        add bx, 10
+        sub cx, 1
+        jnz loop_start
+        ",
+    );
+    assert_eq!(
+        text,
+        "\
+mov cx, 3 ; cx:0x0->0x3 ip:0x0->0x3 \n\
+mov bx, 1000 ; bx:0x0->0x3e8 ip:0x3->0x6 \n\
+add bx, 10 ; bx:0x3e8->0x3f2 ip:0x6->0x9 flags:->A \n\
+sub cx, 1 ; cx:0x3->0x2 ip:0x9->0xc flags:A-> \n\
+jne $-6 ; ip:0xc->0x6 \n\
+add bx, 10 ; bx:0x3f2->0x3fc ip:0x6->0x9 flags:->P \n\
+sub cx, 1 ; cx:0x2->0x1 ip:0x9->0xc flags:P-> \n\
+jne $-6 ; ip:0xc->0x6 \n\
+add bx, 10 ; bx:0x3fc->0x406 ip:0x6->0x9 flags:->PA \n\
+sub cx, 1 ; cx:0x1->0x0 ip:0x9->0xc flags:PA->PZ \n\
+jne $-6 ; ip:0xc->0xe \n
+Final registers:
+      bx: 0x0406 (1030)
+      ip: 0x000e (14)
+   flags: PZ
+"
+    );
+}
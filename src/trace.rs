@@ -0,0 +1,78 @@
+use crate::{Cpu, Flag, Register, StopReason};
+use Register::*;
+
+/// Registers in the order the reference simulator of the course lists them
+const REGISTERS: [Register; 12] = [Ax, Bx, Cx, Dx, Sp, Bp, Si, Di, Es, Cs, Ss, Ds];
+
+/// Runs the loaded program like `Cpu::run`, listing every executed instruction with the
+/// registers, ip and flags it changed, followed by the registers it ended with.
+/// The layout is that of the reference simulator of the course, so runs can be diffed against it.
+pub fn trace(cpu: &mut Cpu, budget: usize) -> (String, StopReason) {
+    let mut text = String::new();
+    let mut before = State::of(cpu);
+    let stop = cpu.run_with(budget, |cpu, instruction| {
+        let after = State::of(cpu);
+        let mut changes = Vec::new();
+        for (i, register) in REGISTERS.into_iter().enumerate() {
+            if before.registers[i] != after.registers[i] {
+                let (old, new) = (before.registers[i], after.registers[i]);
+                changes.push(format!("{register}:0x{old:x}->0x{new:x}"));
+            }
+        }
+        if before.ip != after.ip {
+            changes.push(format!("ip:0x{:x}->0x{:x}", before.ip, after.ip));
+        }
+        if before.flags != after.flags {
+            let (old, new) = (flag_letters(before.flags), flag_letters(after.flags));
+            changes.push(format!("flags:{old}->{new}"));
+        }
+        // Every change is followed by a space, the last one included
+        text.push_str(&format!("{instruction} ; "));
+        for change in changes {
+            text.push_str(&format!("{change} "));
+        }
+        text.push('\n');
+        before = after;
+    });
+
+    text.push_str("\nFinal registers:\n");
+    let state = State::of(cpu);
+    let named = REGISTERS
+        .into_iter()
+        .map(|register| register.to_string())
+        .zip(state.registers)
+        .chain([("ip".to_string(), state.ip)]);
+    for (name, value) in named.filter(|&(_, value)| value != 0) {
+        text.push_str(&format!("{name:>8}: 0x{value:04x} ({value})\n"));
+    }
+    if state.flags != 0 {
+        text.push_str(&format!("   flags: {}\n", flag_letters(state.flags)));
+    }
+    (text, stop)
+}
+
+/// What a trace line reports the changes of
+struct State {
+    registers: [u16; 12],
+    ip: u16,
+    flags: u16,
+}
+impl State {
+    fn of(cpu: &Cpu) -> Self {
+        State {
+            registers: REGISTERS.map(|register| cpu.register(register)),
+            ip: cpu.ip(),
+            flags: cpu.flags(),
+        }
+    }
+}
+
+/// A letter for each flag that is set, from the lowest bit up
+fn flag_letters(flags: u16) -> String {
+    Flag::ALL
+        .into_iter()
+        .zip("CPAZSTIDO".chars())
+        .filter(|&(flag, _)| flags & flag.mask() != 0)
+        .map(|(_, letter)| letter)
+        .collect()
+}